@@ -0,0 +1,485 @@
+//! Compact binary encoding of engine events, for high-volume replay.
+//!
+//! A stream is a sequence of frames, each a little endian `u32` payload length followed by
+//! the payload. A payload starts with a one byte event tag followed by the event fields in
+//! order:
+//!
+//! | tag | event         | fields                                                                |
+//! |-----|---------------|-----------------------------------------------------------------------|
+//! | 1   | `Accepted`    | user id, user order id, sequence                                      |
+//! | 2   | `Rejected`    | user id, user order id, sequence, reason?                             |
+//! | 3   | `Trade`       | buy user id, buy order id, sell user id, sell order id, price, amount, sequence |
+//! | 4   | `TopOfBook`   | side, price?, qty?, sequence, symbol?                                 |
+//! | 5   | `Cancelled`   | user id, user order id, sequence                                      |
+//! | 6   | `Flushed`     | sequence, symbol?                                                     |
+//! | 7   | `Error`       | sequence, message                                                     |
+//! | 8   | `DepthUpdate` | action, side, price, qty, orders, sequence, symbol?                   |
+//! | 9   | `OrderUpdate` | action, side, user id, user order id, price, qty, position, sequence, symbol? |
+//! | 10  | `SelfTradePrevented` | mode, user id, taker order id, maker order id, taker qty, maker qty, sequence |
+//! | 11  | `Triggered`   | user id, user order id, stop price, sequence                          |
+//!
+//! Integers are little endian `u64`s, sides a single `B` or `S` byte, depth actions a single
+//! `A` (add), `C` (change) or `D` (delete) byte, order actions a single `A` (add), `F` (fill),
+//! `R` (reduce) or `D` (delete) byte, self-trade prevention modes a single `N` (cancel
+//! newest), `O` (cancel oldest), `B` (cancel both) or `D` (decrement and cancel) byte and
+//! strings a little endian `u32` byte length followed by UTF-8 bytes. Optional fields, marked
+//! `?`, are preceded by a `0` (absent) or `1` (present) byte.
+//!
+//! A frame holding bytes past the last field of its event is invalid, and readers reject
+//! payloads longer than `MAX_FRAME_LEN` bytes.
+
+use std::convert::TryInto;
+use std::io;
+
+use thiserror::Error;
+
+use crate::core::order::SelfTradePrevention;
+use crate::core::{Side, TradeImpl};
+use crate::event::{DepthAction, EngineEvent, OrderAction};
+
+const ACCEPTED: u8 = 1;
+const REJECTED: u8 = 2;
+const TRADE: u8 = 3;
+const TOP_OF_BOOK: u8 = 4;
+const CANCELLED: u8 = 5;
+const FLUSHED: u8 = 6;
+const ERROR: u8 = 7;
+const DEPTH_UPDATE: u8 = 8;
+const ORDER_UPDATE: u8 = 9;
+const SELF_TRADE_PREVENTED: u8 = 10;
+const TRIGGERED: u8 = 11;
+
+/// Largest payload a reader accepts, well above that of any event.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("unknown event tag `{0}`")]
+    UnknownTag(u8),
+    #[error("invalid side `{0}`")]
+    InvalidSide(u8),
+    #[error("invalid depth action `{0}`")]
+    InvalidDepthAction(u8),
+    #[error("invalid order action `{0}`")]
+    InvalidOrderAction(u8),
+    #[error("invalid self-trade prevention mode `{0}`")]
+    InvalidMode(u8),
+    #[error("invalid option marker `{0}`")]
+    InvalidOption(u8),
+    #[error("frame ends before its last field")]
+    Truncated,
+    #[error("frame of {0} bytes exceeds the maximum of {MAX_FRAME_LEN}")]
+    TooLarge(usize),
+    #[error("frame has {0} trailing bytes")]
+    TrailingBytes(usize),
+    #[error(transparent)]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Appends the frame of an event to `buffer`.
+pub fn encode(event: &EngineEvent, buffer: &mut Vec<u8>) {
+    let start = buffer.len();
+    buffer.extend_from_slice(&[0; 4]);
+
+    match event {
+        EngineEvent::Accepted {
+            user_id,
+            user_order_id,
+            sequence,
+        } => {
+            buffer.push(ACCEPTED);
+            put_u64s(buffer, &[*user_id, *user_order_id, *sequence]);
+        }
+        EngineEvent::Rejected {
+            user_id,
+            user_order_id,
+            reason,
+            sequence,
+        } => {
+            buffer.push(REJECTED);
+            put_u64s(buffer, &[*user_id, *user_order_id, *sequence]);
+            put_option(buffer, reason.as_deref(), put_str);
+        }
+        EngineEvent::Trade(trade) => {
+            buffer.push(TRADE);
+            put_u64s(
+                buffer,
+                &[
+                    trade.buy_user_id,
+                    trade.buy_order_id,
+                    trade.sell_user_id,
+                    trade.sell_order_id,
+                    trade.price,
+                    trade.amount,
+                    trade.sequence,
+                ],
+            );
+        }
+        EngineEvent::SelfTradePrevented {
+            user_id,
+            taker_order_id,
+            maker_order_id,
+            mode,
+            taker_qty,
+            maker_qty,
+            sequence,
+        } => {
+            buffer.push(SELF_TRADE_PREVENTED);
+            buffer.push(match mode {
+                SelfTradePrevention::CancelNewest => b'N',
+                SelfTradePrevention::CancelOldest => b'O',
+                SelfTradePrevention::CancelBoth => b'B',
+                SelfTradePrevention::DecrementAndCancel => b'D',
+            });
+            put_u64s(
+                buffer,
+                &[
+                    *user_id,
+                    *taker_order_id,
+                    *maker_order_id,
+                    *taker_qty,
+                    *maker_qty,
+                    *sequence,
+                ],
+            );
+        }
+        EngineEvent::TopOfBook {
+            side,
+            price,
+            qty,
+            symbol,
+            sequence,
+        } => {
+            buffer.push(TOP_OF_BOOK);
+            put_side(buffer, side);
+            put_option(buffer, *price, put_u64);
+            put_option(buffer, *qty, put_u64);
+            put_u64(buffer, *sequence);
+            put_option(buffer, symbol.as_deref(), put_str);
+        }
+        EngineEvent::DepthUpdate {
+            action,
+            side,
+            price,
+            qty,
+            orders,
+            symbol,
+            sequence,
+        } => {
+            buffer.push(DEPTH_UPDATE);
+            buffer.push(match action {
+                DepthAction::Add => b'A',
+                DepthAction::Change => b'C',
+                DepthAction::Delete => b'D',
+            });
+            put_side(buffer, side);
+            put_u64s(buffer, &[*price, *qty, *orders, *sequence]);
+            put_option(buffer, symbol.as_deref(), put_str);
+        }
+        EngineEvent::OrderUpdate {
+            action,
+            user_id,
+            user_order_id,
+            side,
+            price,
+            qty,
+            position,
+            symbol,
+            sequence,
+        } => {
+            buffer.push(ORDER_UPDATE);
+            buffer.push(match action {
+                OrderAction::Add => b'A',
+                OrderAction::Fill => b'F',
+                OrderAction::Reduce => b'R',
+                OrderAction::Delete => b'D',
+            });
+            put_side(buffer, side);
+            put_u64s(
+                buffer,
+                &[*user_id, *user_order_id, *price, *qty, *position, *sequence],
+            );
+            put_option(buffer, symbol.as_deref(), put_str);
+        }
+        EngineEvent::Triggered {
+            user_id,
+            user_order_id,
+            stop_price,
+            sequence,
+        } => {
+            buffer.push(TRIGGERED);
+            put_u64s(buffer, &[*user_id, *user_order_id, *stop_price, *sequence]);
+        }
+        EngineEvent::Cancelled {
+            user_id,
+            user_order_id,
+            sequence,
+        } => {
+            buffer.push(CANCELLED);
+            put_u64s(buffer, &[*user_id, *user_order_id, *sequence]);
+        }
+        EngineEvent::Flushed { symbol, sequence } => {
+            buffer.push(FLUSHED);
+            put_u64(buffer, *sequence);
+            put_option(buffer, symbol.as_deref(), put_str);
+        }
+        EngineEvent::Error { message, sequence } => {
+            buffer.push(ERROR);
+            put_u64(buffer, *sequence);
+            put_str(buffer, message);
+        }
+    }
+
+    let length = (buffer.len() - start - 4) as u32;
+    buffer[start..start + 4].copy_from_slice(&length.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64s(buffer: &mut Vec<u8>, values: &[u64]) {
+    values.iter().for_each(|value| put_u64(buffer, *value));
+}
+
+fn put_side(buffer: &mut Vec<u8>, side: &Side) {
+    buffer.push(match side {
+        Side::Bid => b'B',
+        Side::Ask => b'S',
+    });
+}
+
+fn put_str(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(value.as_bytes());
+}
+
+fn put_option<T>(buffer: &mut Vec<u8>, value: Option<T>, put: fn(&mut Vec<u8>, T)) {
+    match value {
+        Some(value) => {
+            buffer.push(1);
+            put(buffer, value);
+        }
+        None => buffer.push(0),
+    }
+}
+
+/// Decodes the payload of a single frame, without its length prefix.
+pub fn decode(payload: &[u8]) -> Result<EngineEvent, DecodeError> {
+    let mut payload = Payload(payload);
+
+    let event = match payload.u8()? {
+        ACCEPTED => EngineEvent::Accepted {
+            user_id: payload.u64()?,
+            user_order_id: payload.u64()?,
+            sequence: payload.u64()?,
+        },
+        REJECTED => EngineEvent::Rejected {
+            user_id: payload.u64()?,
+            user_order_id: payload.u64()?,
+            sequence: payload.u64()?,
+            reason: payload.option(Payload::string)?,
+        },
+        TRADE => EngineEvent::Trade(TradeImpl {
+            buy_user_id: payload.u64()?,
+            buy_order_id: payload.u64()?,
+            sell_user_id: payload.u64()?,
+            sell_order_id: payload.u64()?,
+            price: payload.u64()?,
+            amount: payload.u64()?,
+            sequence: payload.u64()?,
+        }),
+        SELF_TRADE_PREVENTED => EngineEvent::SelfTradePrevented {
+            mode: match payload.u8()? {
+                b'N' => SelfTradePrevention::CancelNewest,
+                b'O' => SelfTradePrevention::CancelOldest,
+                b'B' => SelfTradePrevention::CancelBoth,
+                b'D' => SelfTradePrevention::DecrementAndCancel,
+                mode => return Err(DecodeError::InvalidMode(mode)),
+            },
+            user_id: payload.u64()?,
+            taker_order_id: payload.u64()?,
+            maker_order_id: payload.u64()?,
+            taker_qty: payload.u64()?,
+            maker_qty: payload.u64()?,
+            sequence: payload.u64()?,
+        },
+        TOP_OF_BOOK => EngineEvent::TopOfBook {
+            side: payload.side()?,
+            price: payload.option(Payload::u64)?,
+            qty: payload.option(Payload::u64)?,
+            sequence: payload.u64()?,
+            symbol: payload.option(Payload::string)?,
+        },
+        DEPTH_UPDATE => EngineEvent::DepthUpdate {
+            action: match payload.u8()? {
+                b'A' => DepthAction::Add,
+                b'C' => DepthAction::Change,
+                b'D' => DepthAction::Delete,
+                action => return Err(DecodeError::InvalidDepthAction(action)),
+            },
+            side: payload.side()?,
+            price: payload.u64()?,
+            qty: payload.u64()?,
+            orders: payload.u64()?,
+            sequence: payload.u64()?,
+            symbol: payload.option(Payload::string)?,
+        },
+        ORDER_UPDATE => EngineEvent::OrderUpdate {
+            action: match payload.u8()? {
+                b'A' => OrderAction::Add,
+                b'F' => OrderAction::Fill,
+                b'R' => OrderAction::Reduce,
+                b'D' => OrderAction::Delete,
+                action => return Err(DecodeError::InvalidOrderAction(action)),
+            },
+            side: payload.side()?,
+            user_id: payload.u64()?,
+            user_order_id: payload.u64()?,
+            price: payload.u64()?,
+            qty: payload.u64()?,
+            position: payload.u64()?,
+            sequence: payload.u64()?,
+            symbol: payload.option(Payload::string)?,
+        },
+        TRIGGERED => EngineEvent::Triggered {
+            user_id: payload.u64()?,
+            user_order_id: payload.u64()?,
+            stop_price: payload.u64()?,
+            sequence: payload.u64()?,
+        },
+        CANCELLED => EngineEvent::Cancelled {
+            user_id: payload.u64()?,
+            user_order_id: payload.u64()?,
+            sequence: payload.u64()?,
+        },
+        FLUSHED => EngineEvent::Flushed {
+            sequence: payload.u64()?,
+            symbol: payload.option(Payload::string)?,
+        },
+        ERROR => EngineEvent::Error {
+            sequence: payload.u64()?,
+            message: payload.string()?,
+        },
+        tag => return Err(DecodeError::UnknownTag(tag)),
+    };
+
+    match payload.0.len() {
+        0 => Ok(event),
+        trailing => Err(DecodeError::TrailingBytes(trailing)),
+    }
+}
+
+/// Cursor over the unread bytes of a payload.
+struct Payload<'a>(&'a [u8]);
+
+impl Payload<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], DecodeError> {
+        if self.0.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        let bytes = self.take(8)?.try_into().expect("8 bytes were taken");
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn side(&mut self) -> Result<Side, DecodeError> {
+        match self.u8()? {
+            b'B' => Ok(Side::Bid),
+            b'S' => Ok(Side::Ask),
+            side => Err(DecodeError::InvalidSide(side)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let length = u32::from_le_bytes(self.take(4)?.try_into().expect("4 bytes were taken"));
+        let bytes = self.take(length as usize)?.to_vec();
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    fn option<T>(
+        &mut self,
+        read: fn(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Option<T>, DecodeError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => read(self).map(Some),
+            marker => Err(DecodeError::InvalidOption(marker)),
+        }
+    }
+}
+
+/// Reads the events of a binary stream, one `Result` per frame.
+pub struct EventReader<R> {
+    reader: R,
+    failed: bool,
+}
+
+impl<R: io::Read> EventReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            failed: false,
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        let mut length = [0; 4];
+        let mut read = 0;
+        while read < length.len() {
+            match self.reader.read(&mut length[read..]) {
+                // End of the stream, unless it stops within the length prefix.
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(DecodeError::Truncated),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // The length is checked before allocating, as a corrupted one may be huge.
+        let length = u32::from_le_bytes(length) as usize;
+        if length > MAX_FRAME_LEN {
+            return Err(DecodeError::TooLarge(length));
+        }
+        let mut payload = vec![0; length];
+        self.reader
+            .read_exact(&mut payload)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => DecodeError::Truncated,
+                _ => err.into(),
+            })?;
+        Ok(Some(payload))
+    }
+}
+
+impl<R: io::Read> Iterator for EventReader<R> {
+    type Item = Result<EngineEvent, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Frame boundaries are lost after a framing error, so nothing more can be read.
+        if self.failed {
+            return None;
+        }
+
+        match self.next_frame() {
+            Ok(Some(payload)) => Some(decode(&payload)),
+            Ok(None) => None,
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
@@ -1,11 +1,131 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::core::order::SelfTradePrevention;
+use crate::core::PriceBand;
 
 #[derive(Parser, Clone, Debug)]
 pub struct Config {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(short, long, value_name = "ORDER FILE SOURCE")]
     pub input: Option<InputType>,
+    /// Encoding of the order requests
+    #[arg(long, value_enum, default_value_t)]
+    pub input_format: InputFormat,
+    /// Execute crossing orders as trades instead of rejecting them
+    #[arg(short, long)]
+    pub trading: bool,
+    /// Append the order symbol to top of book, depth and order records
+    #[arg(short, long)]
+    pub symbols: bool,
+    /// Publish an `L` record for every price level added, changed or deleted
+    #[arg(long)]
+    pub depth: bool,
+    /// Publish an `O` record for every order added to, filled in, reduced in or deleted from a book
+    #[arg(long)]
+    pub orders: bool,
+    /// Keep the symbol's book in a price ladder, rejecting orders priced outside the band
+    #[arg(long, value_name = "SYMBOL:MIN-MAX", value_parser = parse_price_band)]
+    pub price_band: Vec<(String, PriceBand)>,
+    /// Prevent the user's orders from trading against each other: cancel newest (CN), oldest (CO), both (CB) or decrement and cancel (DC)
+    #[arg(long, value_name = "USER:MODE", value_parser = parse_self_trade_prevention)]
+    pub self_trade_prevention: Vec<(u64, SelfTradePrevention)>,
+    /// Abort with a non-zero exit code on the first malformed input record
+    #[arg(long)]
+    pub strict: bool,
+    /// Where order timestamps are taken from
+    #[arg(long, value_enum, default_value_t)]
+    pub clock: ClockType,
+    /// Encoding of the published events
+    #[arg(long, value_enum, default_value_t)]
+    pub output_format: OutputFormat,
+    /// File the events are written to instead of standard out
+    #[arg(short, long, value_name = "OUTPUT FILE")]
+    pub output: Option<PathBuf>,
+    /// Snapshot the books are restored from before processing
+    #[arg(long, value_name = "SNAPSHOT FILE")]
+    pub snapshot_in: Option<PathBuf>,
+    /// File the books are snapshotted to once every request is processed
+    #[arg(long, value_name = "SNAPSHOT FILE")]
+    pub snapshot_out: Option<PathBuf>,
+    /// Journal every request is appended to before it is processed
+    #[arg(long, value_name = "JOURNAL FILE")]
+    pub journal: Option<PathBuf>,
+    /// Rebuild the books from the snapshot, if any, and the journal before processing
+    #[arg(long, requires = "journal")]
+    pub recover: bool,
+}
+
+fn parse_price_band(s: &str) -> Result<(String, PriceBand), String> {
+    let invalid = || format!("`{s}` is not of the form SYMBOL:MIN-MAX");
+    let (symbol, range) = s.split_once(':').ok_or_else(invalid)?;
+    let (min, max) = range.split_once('-').ok_or_else(invalid)?;
+    let min = min.parse().map_err(|_| invalid())?;
+    let max = max.parse().map_err(|_| invalid())?;
+    let band = PriceBand::new(min, max).ok_or_else(|| {
+        format!(
+            "price band {min}-{max} must be non-empty, above 0 and at most {} prices wide",
+            PriceBand::MAX_TICKS
+        )
+    })?;
+
+    Ok((symbol.to_owned(), band))
+}
+
+fn parse_self_trade_prevention(s: &str) -> Result<(u64, SelfTradePrevention), String> {
+    let invalid = || format!("`{s}` is not of the form USER:MODE");
+    let (user_id, mode) = s.split_once(':').ok_or_else(invalid)?;
+    let user_id = user_id.parse().map_err(|_| invalid())?;
+    let mode = mode.parse().map_err(|err| format!("{err}"))?;
+
+    Ok((user_id, mode))
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Check the output of every `#name:` scenario of an input file against an expected output file
+    Verify {
+        #[arg(value_name = "INPUT FILE")]
+        input: PathBuf,
+        #[arg(value_name = "EXPECTED OUTPUT FILE")]
+        expected: PathBuf,
+        /// Execute crossing orders as trades instead of rejecting them
+        #[arg(short, long)]
+        trading: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ClockType {
+    /// System time when the request is processed
+    Wall,
+    /// Counter ticking once per request, reproducible across runs
+    #[default]
+    Logical,
+    /// Trailing timestamp column of the input records
+    Input,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum InputFormat {
+    /// Positional CSV records
+    #[default]
+    Csv,
+    /// One JSON object per line, tagged with its request type
+    Jsonl,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum OutputFormat {
+    /// Positional CSV records
+    #[default]
+    Csv,
+    /// One JSON object per line, tagged with its event name
+    Jsonl,
+    /// Length-prefixed binary frames
+    Binary,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -0,0 +1,50 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Clock defines where the engine takes order timestamps from.
+pub trait Clock: Send {
+    /// Returns the timestamp for an inbound request, given the timestamp supplied with
+    /// the request, if any.
+    fn now(&mut self, request_timestamp: Option<u128>) -> u128;
+}
+
+/// Stamps requests with the wall clock time in nanoseconds since the unix epoch.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WallClock;
+
+impl Clock for WallClock {
+    fn now(&mut self, _request_timestamp: Option<u128>) -> u128 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time is before the unix epoch")
+            .as_nanos()
+    }
+}
+
+/// Stamps requests with a counter ticking once per request, so replays are reproducible.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogicalClock {
+    tick: u128,
+}
+
+impl Clock for LogicalClock {
+    fn now(&mut self, _request_timestamp: Option<u128>) -> u128 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// Stamps requests with the timestamp supplied with the request. Requests without one
+/// reuse the last seen timestamp.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InputClock {
+    last: u128,
+}
+
+impl Clock for InputClock {
+    fn now(&mut self, request_timestamp: Option<u128>) -> u128 {
+        if let Some(timestamp) = request_timestamp {
+            self.last = timestamp;
+        }
+        self.last
+    }
+}
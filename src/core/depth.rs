@@ -1,98 +1,406 @@
 use std::borrow::Borrow;
-use std::collections::{BTreeMap, VecDeque};
-use std::ops::{Deref, DerefMut, Index, IndexMut};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::{Index, IndexMut};
 
 use either::Either;
+use num::Zero;
+use slab::Slab;
 
 use crate::core::domain::Order;
 use crate::core::Side;
 
-pub struct OrdersByPrice<T: Order>(BTreeMap<<T as Order>::Price, VecDeque<<T as Order>::Id>>);
-pub struct OrdersById<T: Order>(BTreeMap<<T as Order>::Id, T>);
+/// Handle of the queue node of a resting order, among the nodes of its side of the book.
+pub type NodeHandle = usize;
 
-impl<T: Order> Default for OrdersByPrice<T> {
+/// Link of an intrusive doubly linked list queueing the orders of a price level.
+struct Node<Id> {
+    order_id: Id,
+    prev: Option<NodeHandle>,
+    next: Option<NodeHandle>,
+}
+
+/// Slab of the queue nodes of every price level of one side of a book.
+pub struct Nodes<Id>(Slab<Node<Id>>);
+
+impl<Id> Nodes<Id> {
+    /// Links an order at the back of the queue of a level, returning the handle of its node.
+    pub fn push_back<T>(&mut self, level: &mut PriceLevel<T>, order_id: Id) -> NodeHandle
+    where
+        T: Order<Id = Id>,
+    {
+        let handle = self.0.insert(Node {
+            order_id,
+            prev: level.tail,
+            next: None,
+        });
+
+        match level.tail {
+            Some(tail) => self.0[tail].next = Some(handle),
+            None => level.head = Some(handle),
+        }
+        level.tail = Some(handle);
+        level.len += 1;
+
+        handle
+    }
+
+    /// Unlinks a node from the queue of its level, returning the id of its order.
+    pub fn unlink<T>(&mut self, level: &mut PriceLevel<T>, handle: NodeHandle) -> Id
+    where
+        T: Order<Id = Id>,
+    {
+        let node = self.0.remove(handle);
+
+        match node.prev {
+            Some(prev) => self.0[prev].next = node.next,
+            None => level.head = node.next,
+        }
+        match node.next {
+            Some(next) => self.0[next].prev = node.prev,
+            None => level.tail = node.prev,
+        }
+        level.len -= 1;
+
+        node.order_id
+    }
+
+    /// Returns the 0-based position of a node in the queue of its level, walking the queue
+    /// towards its head.
+    pub fn position(&self, handle: NodeHandle) -> usize {
+        std::iter::successors(self.0[handle].prev, |&prev| self.0[prev].prev).count()
+    }
+
+    /// Borrows a level along with the nodes of its queue.
+    pub fn level<'a, T>(&'a self, level: &'a PriceLevel<T>) -> Level<'a, T>
+    where
+        T: Order<Id = Id>,
+    {
+        Level { level, nodes: self }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl<Id> Default for Nodes<Id> {
     fn default() -> Self {
-        Self(Default::default())
+        Self(Slab::new())
     }
 }
 
-impl<T: Order> Deref for OrdersByPrice<T> {
-    type Target = BTreeMap<<T as Order>::Price, VecDeque<<T as Order>::Id>>;
+/// Queue of the orders resting at a single price in their time priority, along with their
+/// total remaining quantity. The nodes of the queue live in the slab of its side.
+pub struct PriceLevel<T: Order> {
+    head: Option<NodeHandle>,
+    tail: Option<NodeHandle>,
+    len: usize,
+    pub quantity: <T as Order>::Amount,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl<T: Order> PriceLevel<T> {
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
     }
 }
 
-impl<T: Order> DerefMut for OrdersByPrice<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+impl<T: Order> Default for PriceLevel<T> {
+    fn default() -> Self {
+        Self {
+            head: None,
+            tail: None,
+            len: 0,
+            quantity: Zero::zero(),
+        }
     }
 }
 
-impl<T: Order> Default for OrdersById<T> {
-    fn default() -> Self {
-        Self(Default::default())
+/// Borrowed price level, able to walk its queue.
+pub struct Level<'a, T: Order> {
+    level: &'a PriceLevel<T>,
+    nodes: &'a Nodes<<T as Order>::Id>,
+}
+
+impl<T: Order> Clone for Level<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Order> Copy for Level<'_, T> {}
+
+impl<'a, T: Order> Level<'a, T> {
+    /// Returns the ids of the orders of the level in their time priority.
+    pub fn iter(self) -> LevelIter<'a, T> {
+        LevelIter {
+            nodes: self.nodes,
+            next: self.level.head,
+        }
+    }
+
+    /// Returns the id of the order at the head of the queue.
+    pub fn front(self) -> Option<&'a <T as Order>::Id> {
+        self.level.head.map(|head| &self.nodes.0[head].order_id)
+    }
+
+    /// Returns the number of orders resting at this price.
+    pub fn len(self) -> usize {
+        self.level.len
+    }
+
+    /// Returns the total remaining quantity of the orders resting at this price.
+    pub fn quantity(self) -> <T as Order>::Amount {
+        self.level.quantity
     }
 }
 
-impl<T: Order> Deref for OrdersById<T> {
-    type Target = BTreeMap<<T as Order>::Id, T>;
-    fn deref(&self) -> &Self::Target {
-        &self.0
+pub struct LevelIter<'a, T: Order> {
+    nodes: &'a Nodes<<T as Order>::Id>,
+    next: Option<NodeHandle>,
+}
+
+impl<'a, T: Order> Iterator for LevelIter<'a, T> {
+    type Item = &'a <T as Order>::Id;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.nodes.0[self.next?];
+        self.next = node.next;
+        Some(&node.order_id)
+    }
+}
+
+/// Price levels of one side of a book, queueing the orders of every level in their time
+/// priority and keeping running totals of the remaining quantity and number of orders across
+/// every level.
+pub trait PriceLevels<T: Order + 'static> {
+    /// Queues an order at the back of its price level, returning the handle of its node.
+    fn push_back(
+        &mut self,
+        price: <T as Order>::Price,
+        order_id: <T as Order>::Id,
+        remaining: <T as Order>::Amount,
+    ) -> NodeHandle;
+
+    /// Unlinks the node of an order with the given remaining quantity from its price level,
+    /// returning the id of the order.
+    fn remove(
+        &mut self,
+        price: <T as Order>::Price,
+        handle: NodeHandle,
+        remaining: <T as Order>::Amount,
+    ) -> <T as Order>::Id;
+
+    /// Returns the running totals of the level at the given price and of the whole side,
+    /// for quantities filled or cancelled off a resting order to be taken out of them.
+    fn totals_mut(
+        &mut self,
+        price: <T as Order>::Price,
+    ) -> (&mut <T as Order>::Amount, &mut <T as Order>::Amount);
+
+    /// Returns the 0-based position of a node in the queue of its level.
+    fn position(&self, handle: NodeHandle) -> usize;
+
+    /// Returns the level at the given price, if any order rests there.
+    fn get(&self, price: <T as Order>::Price) -> Option<Level<'_, T>>;
+
+    /// Returns the price levels, best price first.
+    fn levels(&self) -> impl Iterator<Item = (<T as Order>::Price, Level<'_, T>)> + '_;
+
+    /// Returns the total remaining quantity resting on this side.
+    fn quantity(&self) -> <T as Order>::Amount;
+
+    /// Returns the number of orders resting on this side.
+    fn len(&self) -> usize;
+
+    fn clear(&mut self);
+
+    fn best_level(&self) -> Option<(<T as Order>::Price, Level<'_, T>)> {
+        self.levels().next()
+    }
+
+    /// Returns the id of the order at the head of the best level.
+    fn peek(&self) -> Option<&<T as Order>::Id> {
+        self.best_level()?.1.front()
     }
 }
 
-impl<T: Order> DerefMut for OrdersById<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+/// Price levels of one side of a book in a tree, holding only the prices orders rest at.
+pub struct OrdersByPrice<T: Order> {
+    side: Side,
+    levels: BTreeMap<<T as Order>::Price, PriceLevel<T>>,
+    nodes: Nodes<<T as Order>::Id>,
+    quantity: <T as Order>::Amount,
+    len: usize,
+}
+
+impl<T: Order> OrdersByPrice<T> {
+    pub fn new(side: Side) -> Self {
+        Self {
+            side,
+            levels: Default::default(),
+            nodes: Default::default(),
+            quantity: Zero::zero(),
+            len: 0,
+        }
     }
 }
 
-pub struct OrdersBySide<T: Order> {
-    ask: OrdersByPrice<T>,
-    bid: OrdersByPrice<T>,
+impl<T: Order + 'static> PriceLevels<T> for OrdersByPrice<T> {
+    fn push_back(
+        &mut self,
+        price: <T as Order>::Price,
+        order_id: <T as Order>::Id,
+        remaining: <T as Order>::Amount,
+    ) -> NodeHandle {
+        let level = self.levels.entry(price).or_default();
+        let handle = self.nodes.push_back(level, order_id);
+        level.quantity = level.quantity + remaining;
+        self.quantity = self.quantity + remaining;
+        self.len += 1;
+
+        handle
+    }
+
+    fn remove(
+        &mut self,
+        price: <T as Order>::Price,
+        handle: NodeHandle,
+        remaining: <T as Order>::Amount,
+    ) -> <T as Order>::Id {
+        let level = self
+            .levels
+            .get_mut(&price)
+            .expect("queued orders must rest at their level");
+        let order_id = self.nodes.unlink(level, handle);
+        level.quantity = level.quantity - remaining;
+        self.quantity = self.quantity - remaining;
+        self.len -= 1;
+
+        // This prevents dangling levels (level with no orders).
+        if level.is_empty() {
+            self.levels.remove(&price);
+        }
+
+        order_id
+    }
+
+    fn totals_mut(
+        &mut self,
+        price: <T as Order>::Price,
+    ) -> (&mut <T as Order>::Amount, &mut <T as Order>::Amount) {
+        let level = self
+            .levels
+            .get_mut(&price)
+            .expect("reduced orders must rest at their level");
+
+        (&mut level.quantity, &mut self.quantity)
+    }
+
+    fn position(&self, handle: NodeHandle) -> usize {
+        self.nodes.position(handle)
+    }
+
+    fn get(&self, price: <T as Order>::Price) -> Option<Level<'_, T>> {
+        let level = self.levels.get(&price)?;
+
+        Some(self.nodes.level(level))
+    }
+
+    fn levels(&self) -> impl Iterator<Item = (<T as Order>::Price, Level<'_, T>)> + '_ {
+        let levels = self
+            .levels
+            .iter()
+            .map(move |(&price, level)| (price, self.nodes.level(level)));
+
+        match self.side {
+            Side::Ask => Either::Left(levels),
+            Side::Bid => Either::Right(levels.rev()),
+        }
+    }
+
+    fn quantity(&self) -> <T as Order>::Amount {
+        self.quantity
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.levels.clear();
+        self.nodes.clear();
+        self.quantity = Zero::zero();
+        self.len = 0;
+    }
 }
 
-impl<T: Order> OrdersBySide<T>
+/// Resting orders by id, along with the handle of their queue node.
+pub struct OrdersById<T: Order>(HashMap<<T as Order>::Id, (T, NodeHandle)>);
+
+impl<T: Order> OrdersById<T>
 where
-    T: Order<Side = Side>,
+    <T as Order>::Id: Hash,
 {
-    pub fn iter(
-        &self,
-        side: &<T as Order>::Side,
-    ) -> impl Iterator<Item = &<T as Order>::Id> {
-        match side {
-            Side::Ask => Either::Left(self[side].deref().values().flat_map(VecDeque::iter)),
-            Side::Bid => Either::Right(self[side].deref().values().rev().flat_map(VecDeque::iter)),
-        }
+    pub fn get(&self, order_id: &<T as Order>::Id) -> Option<&T> {
+        self.0.get(order_id).map(|(order, _)| order)
     }
 
-    pub fn peek(&self, side: &<T as Order>::Side) -> Option<&<T as Order>::Id> {
-        self.iter(side).next()
+    pub fn get_mut(&mut self, order_id: &<T as Order>::Id) -> Option<&mut T> {
+        self.0.get_mut(order_id).map(|(order, _)| order)
     }
 
-    pub fn flush(&mut self) {
-        self.bid.clear();
-        self.ask.clear();
+    /// Returns the handle of the queue node of an order.
+    pub fn node(&self, order_id: &<T as Order>::Id) -> Option<NodeHandle> {
+        self.0.get(order_id).map(|&(_, handle)| handle)
+    }
+
+    pub fn contains_key(&self, order_id: &<T as Order>::Id) -> bool {
+        self.0.contains_key(order_id)
+    }
+
+    pub fn insert(&mut self, order: T, handle: NodeHandle) {
+        self.0.insert(order.id(), (order, handle));
+    }
+
+    /// Removes an order, returning it along with the handle of its queue node.
+    pub fn remove(&mut self, order_id: &<T as Order>::Id) -> Option<(T, NodeHandle)> {
+        self.0.remove(order_id)
+    }
+
+    /// Returns every order, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.0.values().map(|(order, _)| order)
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
     }
 }
 
-impl<T: Order> Default for OrdersBySide<T> {
+impl<T: Order> Default for OrdersById<T> {
     fn default() -> Self {
-        Self {
-            ask: Default::default(),
-            bid: Default::default(),
-        }
+        Self(Default::default())
     }
 }
 
-impl<T, S> Index<S> for OrdersBySide<T>
+/// Price levels of both sides of a book.
+pub struct OrdersBySide<L> {
+    ask: L,
+    bid: L,
+}
+
+impl<L> OrdersBySide<L> {
+    pub fn new(ask: L, bid: L) -> Self {
+        Self { ask, bid }
+    }
+}
+
+impl<L, S> Index<S> for OrdersBySide<L>
 where
-    T: Order<Side = Side>,
-    S: Borrow<<T as Order>::Side>,
+    S: Borrow<Side>,
 {
-    type Output = OrdersByPrice<T>;
+    type Output = L;
 
     fn index(&self, side: S) -> &Self::Output {
         match *side.borrow() {
@@ -102,10 +410,9 @@ where
     }
 }
 
-impl<T, S> IndexMut<S> for OrdersBySide<T>
+impl<L, S> IndexMut<S> for OrdersBySide<L>
 where
-    T: Order<Side = Side>,
-    S: Borrow<<T as Order>::Side>,
+    S: Borrow<Side>,
 {
     fn index_mut(&mut self, side: S) -> &mut Self::Output {
         match side.borrow() {
@@ -113,4 +420,4 @@ where
             Side::Bid => &mut self.bid,
         }
     }
-}
\ No newline at end of file
+}
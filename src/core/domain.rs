@@ -1,8 +1,12 @@
-use crate::LogTrait;
+use crate::core::order::SelfTradePrevention;
+use crate::event::EngineEvent;
 use num::Zero;
 use std::ops::{Add, Deref, DerefMut, Sub};
 pub type Spread<T> = (Option<<T as Order>::Price>, Option<<T as Order>::Price>);
 pub type Volume<T> = (<T as Order>::Amount, <T as Order>::Amount);
+pub type Level<T> = (<T as Order>::Price, <T as Order>::Amount);
+/// Price of a level, the total quantity resting at it and the number of orders making it up.
+pub type DepthLevel<T> = (<T as Order>::Price, <T as Order>::Amount, usize);
 
 pub trait Order: PartialOrd {
     type Amount: Add<Output = Self::Amount> + Sub<Output = Self::Amount> + Copy + Ord + Zero;
@@ -16,22 +20,33 @@ pub trait Order: PartialOrd {
     /// Order side.
     type Side: Opposite;
     type OrderStatus: Copy + Eq;
-    type Trade;
+    type Trade: Into<EngineEvent>;
     type TradeError: std::error::Error;
 
-    type Acknowledgment: LogTrait;
+    type Acknowledgment: Into<EngineEvent>;
     /// Return order unique identifier.
     fn id(&self) -> Self::Id;
     fn user_id(&self) -> Self::UserId;
     /// Return order side.
     fn side(&self) -> Self::Side;
     fn remaining(&self) -> Self::Amount;
+    /// Returns the remaining quantity shown in the book, which is only the current peak of
+    /// iceberg orders.
+    fn displayed(&self) -> Self::Amount;
+    /// Shows a new peak of an iceberg order from its reserve.
+    fn replenish(&mut self);
     fn status(&self) -> Self::OrderStatus;
     fn is_closed(&self) -> bool;
     /// Return order limit price.
     fn limit_price(&self) -> Option<Self::Price>;
-    /// Cancel the order.
-    fn cancel(&mut self);
+    /// Returns `true` if the unfilled remainder may rest in the book.
+    fn is_bookable(&self) -> bool;
+    /// Returns `true` if the order must be filled entirely or not at all.
+    fn is_fill_or_kill(&self) -> bool;
+    /// Returns what happens when the order would trade against one of the same user.
+    fn self_trade_prevention(&self) -> Option<SelfTradePrevention>;
+    /// Cancel the order, returning the record of its cancellation.
+    fn cancel(&mut self) -> Self::Acknowledgment;
 
     fn ack(&mut self, reject: bool) -> Self::Acknowledgment;
 }
@@ -44,6 +59,9 @@ where
     fn trade(&mut self, other: &mut Rhs) -> Result<Self::Trade, Self::TradeError>;
     /// Returns `Ok` if orders match.
     fn matches(&self, other: &Rhs) -> Result<(), Self::TradeError>;
+    /// Applies the self-trade prevention of the incoming `other` order instead of trading,
+    /// returning the record of the cancelled quantities, or `None` if the orders may trade.
+    fn prevent_self_trade(&mut self, other: &mut Rhs) -> Option<EngineEvent>;
 }
 
 /// The logical opposite of a value.
@@ -52,6 +70,16 @@ pub trait Opposite<Opposite = Self> {
     fn opposite(&self) -> Opposite;
 }
 
+/// How the matcher treats an incoming order that crosses the opposite side of the book.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchingMode {
+    /// Orders that would make the book crossed are rejected.
+    #[default]
+    Reject,
+    /// Crossing orders are executed against resting orders, producing trades.
+    Trade,
+}
+
 /// Matchers defines the operation to match an incoming order and its respective book and attempts to find a set
 /// of matching trades (bids to asks and vice-versa).
 pub trait Match {
@@ -62,8 +90,7 @@ pub trait Match {
         incoming_order: <B as OrderBook>::Order,
     ) -> Result<Self::Output, Self::Error>
     where
-        B: OrderBook,
-        <<B as OrderBook>::Order as Order>::Acknowledgment: 'static;
+        B: OrderBook;
 }
 
 /// OrderBook defines the operations that can be performed by the order book. It
@@ -104,7 +131,13 @@ pub trait OrderBook {
     /// Gets the bid and ask at the top of the book (head of the bid queue)
     fn peek_top_of_book(&self) -> Spread<Self::Order>;
 
-    /// Returns the number of shares being bid on or offered.
+    /// Returns the best price of the given side together with the total quantity resting at it.
+    fn best_level(&self, side: &<Self::Order as Order>::Side) -> Option<Level<Self::Order>>;
+
+    /// Returns up to `n` price levels of the given side, best price first.
+    fn depth(&self, side: &<Self::Order as Order>::Side, n: usize) -> Vec<DepthLevel<Self::Order>>;
+
+    /// Returns the number of orders resting on the ask and bid side.
     fn len(&self) -> (usize, usize);
 
     /// Returns `true` if the order book contains no items.
@@ -112,8 +145,13 @@ pub trait OrderBook {
         self.len() == (0, 0)
     }
 
+    /// Returns the total displayed quantity resting on the ask and bid side, leaving out the
+    /// reserve of iceberg orders.
     fn volume(&self) -> Volume<Self::Order>;
 
+    /// Returns how crossing orders are handled when matching.
+    fn mode(&self) -> MatchingMode;
+
     /// Attempt to match an incoming order.
     ///
     /// This method takes an order as input and attempts to match it against the
@@ -124,7 +162,6 @@ pub trait OrderBook {
     ) -> Result<<Self::Matching as Match>::Output, <Self::Matching as Match>::Error>
     where
         Self: OrderBook + Sized,
-        <<Self as OrderBook>::Order as Order>::Acknowledgment: 'static,
     {
         <Self::Matching as Match>::matching(self, incoming_order)
     }
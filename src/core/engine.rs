@@ -1,84 +1,966 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 
-use num::Zero;
 use thiserror::Error;
 
-use crate::core::domain::OrderBook;
-use crate::core::order::LimitOrder;
-use crate::core::orderbook::Book;
-use crate::core::{OrderRequest, OrderRequestError};
-use crate::{Acknowledgment, BookTop, LogTrait};
+use crate::core::clock::{Clock, LogicalClock};
+use either::Either;
+
+use crate::core::domain::{DepthLevel, Level, MatchingMode, Order, OrderBook, Spread, Volume};
+use crate::core::ladder::{LadderBook, PriceBand};
+use crate::core::matcher::{DefaultMatchingError, MatchingEngine};
+use crate::core::order::{LimitOrder, OrderId, SelfTradePrevention};
+use crate::core::orderbook::{Book, OrderMut, OrderUpdate};
+use crate::core::snapshot::{BookSnapshot, EngineSnapshot, SnapshotError, SNAPSHOT_VERSION};
+use crate::core::trigger::TriggerBook;
+use crate::core::{OrderRequest, OrderRequestError, Side};
+use crate::event::{DepthAction, EngineEvent};
+use crate::sink::EventSink;
 
 #[derive(Debug, Error)]
 pub enum EngineError {
-    #[error("market order unsupported")]
-    MarketUnsupported,
     #[error(transparent)]
     InboundOrderError(#[from] OrderRequestError),
     #[error(transparent)]
-    ReportingError(#[from] std::sync::mpsc::SendError<Box<dyn LogTrait>>),
+    MatchingError(#[from] DefaultMatchingError),
+    #[error(transparent)]
+    ReportingError(Box<dyn std::error::Error + Send + Sync>),
 }
 
-pub struct Engine {
-    orderbook: Book,
-    log_sender: std::sync::mpsc::Sender<Box<dyn LogTrait>>,
+pub struct Engine<S> {
+    books: BTreeMap<String, SymbolBook>,
+    /// Stop orders of every symbol waiting for its last trade price to reach their stop.
+    triggers: BTreeMap<String, TriggerBook>,
+    last_trade_prices: BTreeMap<String, u64>,
+    mode: MatchingMode,
+    /// Price bands of the symbols whose books are `LadderBook`s.
+    price_bands: BTreeMap<String, PriceBand>,
+    /// Self-trade prevention of the orders of each user that do not set their own.
+    self_trade_preventions: BTreeMap<u64, SelfTradePrevention>,
+    publish_symbol: bool,
+    clock: Box<dyn Clock>,
+    sequence: u64,
+    published_tops: BTreeMap<String, TopOfBook>,
+    /// Depth of every book as last published, tracked only when depth updates are enabled.
+    published_depths: Option<BTreeMap<String, Depth>>,
+    publish_orders: bool,
+    sink: S,
 }
 
-impl Engine {
-    pub fn new(log_sender: std::sync::mpsc::Sender<Box<dyn LogTrait>>) -> Self {
+impl<S: EventSink> Engine<S> {
+    pub fn new(sink: S) -> Self {
+        Self::with_mode(sink, MatchingMode::default())
+    }
+
+    pub fn with_mode(sink: S, mode: MatchingMode) -> Self {
         Self {
-            orderbook: Book::new(),
-            log_sender,
+            books: BTreeMap::new(),
+            triggers: BTreeMap::new(),
+            last_trade_prices: BTreeMap::new(),
+            mode,
+            price_bands: BTreeMap::new(),
+            self_trade_preventions: BTreeMap::new(),
+            publish_symbol: false,
+            clock: Box::<LogicalClock>::default(),
+            sequence: 0,
+            published_tops: BTreeMap::new(),
+            published_depths: None,
+            publish_orders: false,
+            sink,
         }
     }
 
+    /// Replaces the clock order timestamps are taken from; a `LogicalClock` by default.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Returns the sequence number of the last processed request.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Consumes the engine, returning its sink with every event published so far.
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    /// Appends the order symbol to every published top of book record.
+    pub fn with_symbol_output(mut self, publish_symbol: bool) -> Self {
+        self.publish_symbol = publish_symbol;
+        self
+    }
+
+    /// Publishes a depth update for every price level added, changed or deleted by a request.
+    pub fn with_depth_output(mut self, publish_depth: bool) -> Self {
+        self.published_depths = publish_depth.then(|| {
+            self.books
+                .iter()
+                .map(|(symbol, book)| (symbol.clone(), Depth::of(book)))
+                .collect()
+        });
+        self.books = std::mem::take(&mut self.books)
+            .into_iter()
+            .map(|(symbol, book)| (symbol, book.with_touched_levels(publish_depth)))
+            .collect();
+        self
+    }
+
+    /// Publishes an order update for every order added to, partially filled in, reduced in
+    /// or deleted from a book.
+    pub fn with_order_output(mut self, publish_orders: bool) -> Self {
+        self.publish_orders = publish_orders;
+        self.books = std::mem::take(&mut self.books)
+            .into_iter()
+            .map(|(symbol, book)| (symbol, book.with_order_updates(publish_orders)))
+            .collect();
+        self
+    }
+
+    /// Keeps the symbol's book in a `LadderBook` over the given price band, rejecting orders
+    /// priced outside of it. Applies to books created or restored from then on.
+    pub fn with_price_band(mut self, symbol: impl Into<String>, band: PriceBand) -> Self {
+        self.price_bands.insert(symbol.into(), band);
+        self
+    }
+
+    /// Prevents the orders of the user from trading against each other, unless an order sets
+    /// its own self-trade prevention.
+    pub fn with_self_trade_prevention(mut self, user_id: u64, mode: SelfTradePrevention) -> Self {
+        self.self_trade_preventions.insert(user_id, mode);
+        self
+    }
+
+    /// Captures the resting orders of every book, the stop orders waiting to be triggered,
+    /// the last trade prices and the last sequence number.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        EngineSnapshot {
+            version: SNAPSHOT_VERSION,
+            sequence: self.sequence,
+            books: self
+                .books
+                .iter()
+                .map(|(symbol, book)| (symbol.clone(), book.snapshot()))
+                .collect(),
+            stops: self
+                .triggers
+                .iter()
+                .filter(|(_, triggers)| !triggers.is_empty())
+                .map(|(symbol, triggers)| (symbol.clone(), triggers.orders().cloned().collect()))
+                .collect(),
+            last_trade_prices: self.last_trade_prices.clone(),
+        }
+    }
+
+    /// Replaces every book with the ones of the snapshot and continues its sequence numbers.
+    /// The restored tops of book and depths count as published, so only later changes are
+    /// published.
+    pub fn restore(&mut self, snapshot: EngineSnapshot) -> Result<(), SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+
+        let mut books = BTreeMap::new();
+        for (symbol, book) in snapshot.books {
+            let book = SymbolBook::restore(self.price_bands.get(&symbol), self.mode, book)?
+                .with_order_updates(self.publish_orders)
+                .with_touched_levels(self.published_depths.is_some());
+            // Order ids are unique across books, as cancels are routed by id alone.
+            if let Some(order) = book.orders().find(|order| contains(&books, &order.id())) {
+                return Err(SnapshotError::DuplicateOrder(order.id()));
+            }
+            books.insert(symbol, book);
+        }
+
+        let mut triggers = BTreeMap::new();
+        for (symbol, orders) in snapshot.stops {
+            let mut symbol_triggers = TriggerBook::default();
+            for order in orders {
+                if order.stop_price.is_none() {
+                    return Err(SnapshotError::MisplacedOrder(order.id()));
+                }
+                let order_id = order.id();
+                if contains(&books, &order_id)
+                    || symbol_triggers.get(&order_id).is_some()
+                    || triggers
+                        .values()
+                        .any(|triggers: &TriggerBook| triggers.get(&order_id).is_some())
+                {
+                    return Err(SnapshotError::DuplicateOrder(order_id));
+                }
+                symbol_triggers.insert(order);
+            }
+            triggers.insert(symbol, symbol_triggers);
+        }
+
+        self.published_tops = books
+            .iter()
+            .map(|(symbol, book)| {
+                let top = TopOfBook {
+                    bid: book.best_level(&Side::Bid),
+                    ask: book.best_level(&Side::Ask),
+                };
+                (symbol.clone(), top)
+            })
+            .collect();
+        if let Some(published_depths) = &mut self.published_depths {
+            *published_depths = books
+                .iter()
+                .map(|(symbol, book)| (symbol.clone(), Depth::of(book)))
+                .collect();
+        }
+        self.books = books;
+        self.triggers = triggers;
+        self.last_trade_prices = snapshot.last_trade_prices;
+        self.sequence = snapshot.sequence;
+
+        Ok(())
+    }
+
     pub fn process(&mut self, incoming_order: OrderRequest) -> Result<(), EngineError> {
+        self.sequence += 1;
+
         match incoming_order {
-            OrderRequest::Create { price, .. } => {
-                if price.is_zero() {
-                    Err(EngineError::MarketUnsupported)?;
+            OrderRequest::Create {
+                user_id,
+                user_order_id,
+                ref symbol,
+                price,
+                peak_size,
+                stop_price,
+                unix_nano,
+                ..
+            } => {
+                if self.contains(&OrderId::new(user_id, user_order_id)) {
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some("duplicate order id".to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                    return Ok(());
+                }
+                // Market orders never rest, so only limit prices must fit the band.
+                if price != 0 && !self.in_price_band(symbol, price) {
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some("price out of band".to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                    return Ok(());
+                }
+                if peak_size == Some(0) {
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some("invalid peak size".to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                    return Ok(());
+                }
+                if stop_price == Some(0) {
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some("invalid stop price".to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                    return Ok(());
+                }
+                // Without trades there is no last trade price to trigger stop orders.
+                if stop_price.is_some() && self.mode == MatchingMode::Reject {
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some("stop orders require trade mode".to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                    return Ok(());
                 }
 
-                let order = LimitOrder::try_from(incoming_order)?;
-                if let Ok((r, accepted)) = self.orderbook.matching(order) {
-                    self.log_sender.send(r)?;
-                    if accepted {
-                        let (ask_volume, bib_volume) = self.orderbook.volume();
-                        let (side, qty, price) = match self.orderbook.peek_top_of_book() {
-                            (Some(ask_price), Some(bid_price)) => {
-                                if ask_price > bid_price {
-                                    ("S", ask_volume, ask_price)
-                                } else {
-                                    ("B", bib_volume, bid_price)
-                                }
-                            }
-                            (Some(ask_price), None) => ("S", ask_volume, ask_price),
-                            (None, Some(bid_price)) => ("B", bib_volume, bid_price),
-                            _ => ("-", 0, 0),
-                        };
-                        self.log_sender.send(Box::new(BookTop {
-                            label: "B".to_owned(),
-                            side: side.to_string(),
-                            price,
-                            total_qty: qty,
-                        }))?;
-                    }
+                let mut order = LimitOrder::try_from(incoming_order)?;
+                order.timestamp = self.clock.now(unix_nano);
+                order.sequence = self.sequence;
+                order.self_trade_prevention = order
+                    .self_trade_prevention
+                    .or_else(|| self.self_trade_preventions.get(&user_id).copied());
+
+                let symbol = order.order_symbol.clone();
+                if order.stop_price.is_some() {
+                    self.triggers
+                        .entry(symbol.clone())
+                        .or_default()
+                        .insert(order);
+                    self.publish(EngineEvent::Accepted {
+                        user_id,
+                        user_order_id,
+                        sequence: self.sequence,
+                    })?;
+                } else {
+                    let (records, _) = self.book_mut(&symbol).matching(order)?;
+                    self.publish_matched(&symbol, records)?;
                 }
+                // A stop order may be triggered on entry by the last trade price already.
+                self.trigger_stops(&symbol)?;
+                self.publish_market_data(&symbol)?;
             }
-            OrderRequest::Cancel { user_order_id, .. } => {
-                if let Some(canceled_order) = self.orderbook.cancel(&user_order_id) {
-                    self.log_sender.send(Box::new(Acknowledgment {
-                        label: "A".to_owned(),
+            OrderRequest::Cancel {
+                user_id,
+                user_order_id,
+                ..
+            } => {
+                let order_id = OrderId::new(user_id, user_order_id);
+                let canceled_order = self
+                    .books
+                    .values_mut()
+                    .find_map(|book| book.cancel(&order_id))
+                    .or_else(|| {
+                        self.triggers
+                            .values_mut()
+                            .find_map(|triggers| triggers.cancel(&order_id))
+                    });
+
+                if let Some(canceled_order) = canceled_order {
+                    self.publish(EngineEvent::Cancelled {
                         user_id: canceled_order.user_id,
                         user_order_id: canceled_order.order_id,
-                    }))?;
+                        sequence: self.sequence,
+                    })?;
+                    self.publish_market_data(&canceled_order.order_symbol)?;
+                } else {
+                    let reason = self.missing_order_reason(user_order_id);
+                    self.publish(EngineEvent::Rejected {
+                        user_id,
+                        user_order_id,
+                        reason: Some(reason.to_owned()),
+                        sequence: self.sequence,
+                    })?;
+                }
+            }
+            OrderRequest::Amend {
+                user_id,
+                user_order_id,
+                price,
+                qty,
+                unix_nano,
+            } => {
+                self.amend(user_id, user_order_id, price, qty, unix_nano)?;
+            }
+            // Flushing starts the book over, along with its stop orders and last trade price, so
+            // its top is published afresh by the next order. No eliminated top is published, as
+            // the reference output ends every scenario with a silent flush. Its levels and
+            // resting orders are deleted one by one after the flush event, for depth and order
+            // update consumers.
+            OrderRequest::FlushBook { symbol } => {
+                match &symbol {
+                    Some(symbol) => {
+                        if let Some(book) = self.books.get_mut(symbol) {
+                            book.flush();
+                        }
+                        self.triggers.remove(symbol);
+                        self.last_trade_prices.remove(symbol);
+                        self.published_tops.remove(symbol);
+                    }
+                    None => {
+                        self.books.values_mut().for_each(SymbolBook::flush);
+                        self.triggers.clear();
+                        self.last_trade_prices.clear();
+                        self.published_tops.clear();
+                    }
+                }
+                let flushed_symbols: Vec<_> = match &symbol {
+                    Some(symbol) => vec![symbol.clone()],
+                    None => self.books.keys().cloned().collect(),
+                };
+                self.publish(EngineEvent::Flushed {
+                    symbol,
+                    sequence: self.sequence,
+                })?;
+                for symbol in flushed_symbols {
+                    self.publish_depth(&symbol)?;
+                    self.publish_orders(&symbol)?;
+                }
+            }
+            OrderRequest::EndOfSession => {
+                let mut purged_symbols = Vec::new();
+                for (symbol, book) in self.books.iter_mut() {
+                    let purged_orders = book.purge_day_orders();
+                    if purged_orders.is_empty() {
+                        continue;
+                    }
+                    for purged_order in purged_orders {
+                        publish(
+                            &mut self.sink,
+                            EngineEvent::Cancelled {
+                                user_id: purged_order.user_id,
+                                user_order_id: purged_order.order_id,
+                                sequence: self.sequence,
+                            },
+                        )?;
+                    }
+                    purged_symbols.push(symbol.clone());
+                }
+                // Stop orders are not in any book yet, so purging them changes no market data.
+                for triggers in self.triggers.values_mut() {
+                    for purged_order in triggers.purge_day_orders() {
+                        publish(
+                            &mut self.sink,
+                            EngineEvent::Cancelled {
+                                user_id: purged_order.user_id,
+                                user_order_id: purged_order.order_id,
+                                sequence: self.sequence,
+                            },
+                        )?;
+                    }
+                }
+                for symbol in purged_symbols {
+                    self.publish_market_data(&symbol)?;
                 }
             }
-            OrderRequest::FlushBook => {
-                self.orderbook.flush();
+        };
+
+        Ok(())
+    }
+
+    /// Processes a journaled request under the sequence number it was first processed with,
+    /// which is ahead of the engine's when requests in between were malformed.
+    pub fn replay(&mut self, sequence: u64, request: OrderRequest) -> Result<(), EngineError> {
+        assert!(
+            sequence > self.sequence,
+            "replayed requests must follow the processed ones"
+        );
+        self.sequence = sequence - 1;
+        self.process(request)
+    }
+
+    fn amend(
+        &mut self,
+        user_id: u64,
+        user_order_id: u64,
+        price: u64,
+        qty: u64,
+        unix_nano: Option<u128>,
+    ) -> Result<(), EngineError> {
+        let sequence = self.sequence;
+        let reject = |reason: Option<&str>| EngineEvent::Rejected {
+            user_id,
+            user_order_id,
+            reason: reason.map(str::to_owned),
+            sequence,
+        };
+
+        let order_id = OrderId::new(user_id, user_order_id);
+        // Stop orders are only amended once triggered into a book.
+        if self
+            .triggers
+            .values()
+            .any(|triggers| triggers.get(&order_id).is_some())
+        {
+            self.publish(reject(Some("invalid amend")))?;
+            return Ok(());
+        }
+        let Some((symbol, book)) = self
+            .books
+            .iter_mut()
+            .find(|(_, book)| book.get(&order_id).is_some())
+        else {
+            let reason = self.missing_order_reason(user_order_id);
+            self.publish(reject(Some(reason)))?;
+            return Ok(());
+        };
+        let order = book.get(&order_id).expect("book was found by this order");
+
+        // Amends cannot turn an order into a market order nor cancel its filled amount.
+        if price == 0 || qty <= order.filled {
+            self.publish(reject(Some("invalid amend")))?;
+            return Ok(());
+        }
+        if book.band().is_some_and(|band| !band.contains(price)) {
+            self.publish(reject(Some("price out of band")))?;
+            return Ok(());
+        }
+
+        let symbol = symbol.clone();
+        if price == order.price && qty <= order.quantity {
+            // A size decrease keeps the order's time priority.
+            book.reduce(&order_id, qty);
+            self.publish(EngineEvent::Accepted {
+                user_id,
+                user_order_id,
+                sequence: self.sequence,
+            })?;
+            return self.publish_market_data(&symbol);
+        }
+
+        let mut amended_order = order.clone();
+        amended_order.price = price;
+        amended_order.quantity = qty;
+        amended_order.timestamp = self.clock.now(unix_nano);
+        amended_order.sequence = self.sequence;
+
+        if self.mode == MatchingMode::Reject && MatchingEngine::crosses(&*book, &amended_order) {
+            self.publish(reject(None))?;
+            return Ok(());
+        }
+
+        // A new price or a larger size re-queues the order at the back, possibly matching it.
+        book.cancel(&order_id);
+        let (records, _) = book.matching(amended_order)?;
+        self.publish_matched(&symbol, records)?;
+        self.trigger_stops(&symbol)?;
+        self.publish_market_data(&symbol)?;
+
+        Ok(())
+    }
+
+    /// Returns the symbol's book, creating it on its first order.
+    fn book_mut(&mut self, symbol: &str) -> &mut SymbolBook {
+        let (mode, publish_orders) = (self.mode, self.publish_orders);
+        let publish_depth = self.published_depths.is_some();
+        let band = self.price_bands.get(symbol).copied();
+
+        self.books.entry(symbol.to_owned()).or_insert_with(|| {
+            SymbolBook::new(band, mode)
+                .with_order_updates(publish_orders)
+                .with_touched_levels(publish_depth)
+        })
+    }
+
+    /// Publishes the records of a matched order, keeping the price of the last trade of the
+    /// symbol.
+    fn publish_matched(
+        &mut self,
+        symbol: &str,
+        records: Vec<EngineEvent>,
+    ) -> Result<(), EngineError> {
+        for event in records {
+            if let EngineEvent::Trade(trade) = &event {
+                self.last_trade_prices
+                    .insert(symbol.to_owned(), trade.price);
+            }
+            self.publish(event)?;
+        }
+
+        Ok(())
+    }
+
+    /// Matches the stop orders of the symbol triggered by its last trade price, one at a
+    /// time, as the trades of each may trigger more. Triggered orders take the sequence number
+    /// of the request triggering them.
+    fn trigger_stops(&mut self, symbol: &str) -> Result<(), EngineError> {
+        while let Some(mut order) = self.next_triggered(symbol) {
+            let stop_price = order
+                .stop_price
+                .take()
+                .expect("triggered orders must have a stop price");
+            order.sequence = self.sequence;
+            self.publish(EngineEvent::Triggered {
+                user_id: order.user_id,
+                user_order_id: order.order_id,
+                stop_price,
+                sequence: self.sequence,
+            })?;
+
+            // Stop orders were acknowledged on entry, so only a reject is published again.
+            let (mut records, _) = self.book_mut(symbol).matching(order)?;
+            records.retain(|event| !matches!(event, EngineEvent::Accepted { .. }));
+            self.publish_matched(symbol, records)?;
+        }
+
+        Ok(())
+    }
+
+    fn next_triggered(&mut self, symbol: &str) -> Option<LimitOrder> {
+        let last_trade_price = *self.last_trade_prices.get(symbol)?;
+
+        self.triggers.get_mut(symbol)?.trigger(last_trade_price)
+    }
+
+    /// Reports a request the engine could not process, e.g. a malformed input record, as an
+    /// error event stamped with the next sequence number.
+    pub fn publish_error(&mut self, message: impl Into<String>) -> Result<(), EngineError> {
+        self.sequence += 1;
+        self.publish(EngineEvent::Error {
+            sequence: self.sequence,
+            message: message.into(),
+        })
+    }
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), EngineError> {
+        publish(&mut self.sink, event)
+    }
+
+    /// Returns `true` if any book holds a resting order, or a stop order waits to be
+    /// triggered, with the given id.
+    fn contains(&self, order_id: &OrderId) -> bool {
+        contains(&self.books, order_id)
+            || self
+                .triggers
+                .values()
+                .any(|triggers| triggers.get(order_id).is_some())
+    }
+
+    /// Explains why a user's order id could not be found: either nobody rests an order
+    /// under that user order id, or it belongs to another user.
+    fn missing_order_reason(&self, user_order_id: u64) -> &'static str {
+        let owned_by_other_user = self
+            .books
+            .values()
+            .any(|book| book.contains_user_order_id(user_order_id))
+            || self
+                .triggers
+                .values()
+                .any(|triggers| triggers.contains_user_order_id(user_order_id));
+
+        if owned_by_other_user {
+            "not order owner"
+        } else {
+            "unknown order"
+        }
+    }
+
+    /// Returns `true` if the symbol has no price band or the price lies within it.
+    fn in_price_band(&self, symbol: &str, price: u64) -> bool {
+        self.price_bands
+            .get(symbol)
+            .is_none_or(|band| band.contains(price))
+    }
+
+    /// Publishes the changes to the top of the symbol's book, followed by the changes to its
+    /// depth and its orders when enabled.
+    fn publish_market_data(&mut self, symbol: &str) -> Result<(), EngineError> {
+        self.publish_top_of_book(symbol)?;
+        self.publish_depth(symbol)?;
+        self.publish_orders(symbol)
+    }
+
+    /// Publishes a top of book record for every side of the symbol's book whose best price
+    /// or quantity at that price changed since it was last published.
+    fn publish_top_of_book(&mut self, symbol: &str) -> Result<(), EngineError> {
+        let Some(book) = self.books.get(symbol) else {
+            return Ok(());
+        };
+
+        let current = TopOfBook {
+            bid: book.best_level(&Side::Bid),
+            ask: book.best_level(&Side::Ask),
+        };
+        let published = self.published_tops.entry(symbol.to_owned()).or_default();
+        let previous = std::mem::replace(published, current);
+
+        for (side, previous_level, current_level) in [
+            (Side::Bid, previous.bid, current.bid),
+            (Side::Ask, previous.ask, current.ask),
+        ] {
+            if previous_level == current_level {
+                continue;
             }
+
+            self.publish(EngineEvent::TopOfBook {
+                side,
+                price: current_level.map(|(price, _)| price),
+                qty: current_level.map(|(_, qty)| qty),
+                symbol: self.publish_symbol.then(|| symbol.to_owned()),
+                sequence: self.sequence,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Publishes the changes to the resting orders of the symbol's book, in the order they
+    /// happened.
+    fn publish_orders(&mut self, symbol: &str) -> Result<(), EngineError> {
+        let Some(book) = self.books.get_mut(symbol) else {
+            return Ok(());
         };
 
+        for update in book.take_order_updates() {
+            publish(
+                &mut self.sink,
+                EngineEvent::OrderUpdate {
+                    action: update.action,
+                    user_id: update.order_id.user_id,
+                    user_order_id: update.order_id.user_order_id,
+                    side: update.side,
+                    price: update.price,
+                    qty: update.remaining,
+                    position: update.position as u64,
+                    symbol: self.publish_symbol.then(|| symbol.to_owned()),
+                    sequence: self.sequence,
+                },
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Publishes a depth update for every price level of the symbol's book that was added,
+    /// changed or deleted since the depth was last published, bids first, each side from the
+    /// lowest to the highest price. Only the levels the book recorded as touched are compared.
+    fn publish_depth(&mut self, symbol: &str) -> Result<(), EngineError> {
+        let (Some(book), Some(published_depths)) =
+            (self.books.get_mut(symbol), self.published_depths.as_mut())
+        else {
+            return Ok(());
+        };
+
+        let touched_levels = book.take_touched_levels();
+        let published = published_depths.entry(symbol.to_owned()).or_default();
+
+        for side in [Side::Bid, Side::Ask] {
+            let published_levels = published.side_mut(side);
+            let prices = touched_levels
+                .iter()
+                .filter(|&&(level_side, _)| level_side == side)
+                .map(|&(_, price)| price);
+            for price in prices {
+                let current = book
+                    .level(&side, price)
+                    .map(|(_, qty, orders)| (qty, orders as u64));
+                let (action, (qty, orders)) = match (published_levels.get(&price), current) {
+                    (None, Some(level)) => (DepthAction::Add, level),
+                    (Some(&previous), Some(level)) if previous != level => {
+                        (DepthAction::Change, level)
+                    }
+                    (Some(_), None) => (DepthAction::Delete, (0, 0)),
+                    _ => continue,
+                };
+                match current {
+                    Some(level) => published_levels.insert(price, level),
+                    None => published_levels.remove(&price),
+                };
+
+                publish(
+                    &mut self.sink,
+                    EngineEvent::DepthUpdate {
+                        action,
+                        side,
+                        price,
+                        qty,
+                        orders,
+                        symbol: self.publish_symbol.then(|| symbol.to_owned()),
+                        sequence: self.sequence,
+                    },
+                )?;
+            }
+        }
+
         Ok(())
     }
 }
+
+fn contains(books: &BTreeMap<String, SymbolBook>, order_id: &OrderId) -> bool {
+    books.values().any(|book| book.get(order_id).is_some())
+}
+
+fn publish<S: EventSink>(sink: &mut S, event: EngineEvent) -> Result<(), EngineError> {
+    sink.publish(event)
+        .map_err(|err| EngineError::ReportingError(Box::new(err)))
+}
+
+/// Price levels of each side of a book, mapping their price to their total quantity and
+/// order count.
+#[derive(Debug, Default)]
+struct Depth {
+    bid: BTreeMap<u64, (u64, u64)>,
+    ask: BTreeMap<u64, (u64, u64)>,
+}
+
+impl Depth {
+    fn of(book: &SymbolBook) -> Self {
+        let levels = |side| {
+            book.depth(&side, usize::MAX)
+                .into_iter()
+                .map(|(price, qty, orders)| (price, (qty, orders as u64)))
+                .collect()
+        };
+
+        Self {
+            bid: levels(Side::Bid),
+            ask: levels(Side::Ask),
+        }
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<u64, (u64, u64)> {
+        match side {
+            Side::Bid => &mut self.bid,
+            Side::Ask => &mut self.ask,
+        }
+    }
+}
+
+/// Best price level, with its total quantity, of each side of a book.
+#[derive(Clone, Copy, Debug, Default)]
+struct TopOfBook {
+    bid: Option<(u64, u64)>,
+    ask: Option<(u64, u64)>,
+}
+
+/// Book of a symbol, kept in a `LadderBook` when the symbol has a price band.
+enum SymbolBook {
+    Tree(Book),
+    Ladder(LadderBook),
+}
+
+/// Forwards a call to the book, whichever its kind.
+macro_rules! delegate {
+    ($book:expr, $inner:ident => $call:expr) => {
+        match $book {
+            SymbolBook::Tree($inner) => $call,
+            SymbolBook::Ladder($inner) => $call,
+        }
+    };
+}
+
+impl SymbolBook {
+    fn new(band: Option<PriceBand>, mode: MatchingMode) -> Self {
+        match band {
+            Some(band) => Self::Ladder(LadderBook::with_mode(band, mode)),
+            None => Self::Tree(Book::with_mode(mode)),
+        }
+    }
+
+    fn restore(
+        band: Option<&PriceBand>,
+        mode: MatchingMode,
+        snapshot: BookSnapshot,
+    ) -> Result<Self, SnapshotError> {
+        Ok(match band {
+            Some(&band) => Self::Ladder(LadderBook::restore(band, mode, snapshot)?),
+            None => Self::Tree(Book::restore(mode, snapshot)?),
+        })
+    }
+
+    fn band(&self) -> Option<PriceBand> {
+        match self {
+            Self::Tree(_) => None,
+            Self::Ladder(book) => Some(book.band()),
+        }
+    }
+
+    fn with_order_updates(self, record: bool) -> Self {
+        match self {
+            Self::Tree(book) => Self::Tree(book.with_order_updates(record)),
+            Self::Ladder(book) => Self::Ladder(book.with_order_updates(record)),
+        }
+    }
+
+    fn take_order_updates(&mut self) -> Vec<OrderUpdate> {
+        delegate!(self, book => book.take_order_updates())
+    }
+
+    fn with_touched_levels(self, record: bool) -> Self {
+        match self {
+            Self::Tree(book) => Self::Tree(book.with_touched_levels(record)),
+            Self::Ladder(book) => Self::Ladder(book.with_touched_levels(record)),
+        }
+    }
+
+    fn take_touched_levels(&mut self) -> BTreeSet<(Side, u64)> {
+        delegate!(self, book => book.take_touched_levels())
+    }
+
+    fn level(&self, side: &Side, price: u64) -> Option<DepthLevel<LimitOrder>> {
+        delegate!(self, book => book.level(side, price))
+    }
+
+    fn flush(&mut self) {
+        delegate!(self, book => book.flush())
+    }
+
+    fn get(&self, order_id: &OrderId) -> Option<&LimitOrder> {
+        delegate!(self, book => book.get(order_id))
+    }
+
+    fn contains_user_order_id(&self, user_order_id: u64) -> bool {
+        delegate!(self, book => book.contains_user_order_id(user_order_id))
+    }
+
+    fn orders(&self) -> impl Iterator<Item = &LimitOrder> {
+        match self {
+            Self::Tree(book) => Either::Left(book.orders()),
+            Self::Ladder(book) => Either::Right(book.orders()),
+        }
+    }
+
+    fn reduce(&mut self, order_id: &OrderId, quantity: u64) -> Option<&LimitOrder> {
+        delegate!(self, book => book.reduce(order_id, quantity))
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        delegate!(self, book => book.snapshot())
+    }
+
+    fn purge_day_orders(&mut self) -> Vec<LimitOrder> {
+        delegate!(self, book => book.purge_day_orders())
+    }
+}
+
+impl OrderBook for SymbolBook {
+    type Matching = MatchingEngine;
+    type Order = LimitOrder;
+    type OrderRef<'e> = &'e LimitOrder where Self: 'e;
+    type OrderRefMut<'e> = OrderMut<'e> where Self: 'e;
+
+    fn iter(&self, side: &Side) -> impl Iterator<Item = Self::OrderRef<'_>> + '_ {
+        match self {
+            Self::Tree(book) => Either::Left(book.iter(side)),
+            Self::Ladder(book) => Either::Right(book.iter(side)),
+        }
+    }
+
+    fn place(&mut self, order: LimitOrder) {
+        delegate!(self, book => book.place(order))
+    }
+
+    fn cancel(&mut self, order_id: &OrderId) -> Option<LimitOrder> {
+        delegate!(self, book => book.cancel(order_id))
+    }
+
+    fn peek(&self, side: &Side) -> Option<Self::OrderRef<'_>> {
+        delegate!(self, book => book.peek(side))
+    }
+
+    fn peek_mut(&mut self, side: &Side) -> Option<Self::OrderRefMut<'_>> {
+        delegate!(self, book => book.peek_mut(side))
+    }
+
+    fn pop(&mut self, side: &Side) -> Option<LimitOrder> {
+        delegate!(self, book => book.pop(side))
+    }
+
+    fn peek_top_of_book(&self) -> Spread<LimitOrder> {
+        delegate!(self, book => book.peek_top_of_book())
+    }
+
+    fn best_level(&self, side: &Side) -> Option<Level<LimitOrder>> {
+        delegate!(self, book => book.best_level(side))
+    }
+
+    fn depth(&self, side: &Side, n: usize) -> Vec<DepthLevel<LimitOrder>> {
+        delegate!(self, book => book.depth(side, n))
+    }
+
+    fn len(&self) -> (usize, usize) {
+        delegate!(self, book => book.len())
+    }
+
+    fn volume(&self) -> Volume<LimitOrder> {
+        delegate!(self, book => book.volume())
+    }
+
+    fn mode(&self) -> MatchingMode {
+        delegate!(self, book => book.mode())
+    }
+}
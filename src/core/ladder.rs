@@ -0,0 +1,239 @@
+use either::Either;
+use num::Zero;
+
+use crate::core::depth::{Level, NodeHandle, Nodes, PriceLevel, PriceLevels};
+use crate::core::domain::{MatchingMode, Order};
+use crate::core::order::LimitOrder;
+use crate::core::orderbook::LevelBook;
+use crate::core::snapshot::{BookSnapshot, SnapshotError};
+use crate::core::Side;
+
+/// Inclusive range of the prices orders may rest at in a `LadderBook`, one level per price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceBand {
+    min: u64,
+    max: u64,
+}
+
+impl PriceBand {
+    /// Most prices a band may span, as a ladder allocates a level for each of them up front.
+    pub const MAX_TICKS: u64 = 1 << 16;
+
+    /// Returns the band from `min` to `max`, or `None` if it is empty, includes the market
+    /// order price `0` or spans more than `MAX_TICKS` prices.
+    pub fn new(min: u64, max: u64) -> Option<Self> {
+        (min > 0 && min <= max && max - min < Self::MAX_TICKS).then_some(Self { min, max })
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    /// Returns `true` if an order may rest at the given price.
+    pub fn contains(&self, price: u64) -> bool {
+        (self.min..=self.max).contains(&price)
+    }
+
+    fn ticks(&self) -> usize {
+        (self.max - self.min + 1) as usize
+    }
+
+    fn offset(&self, price: u64) -> usize {
+        assert!(
+            self.contains(price),
+            "ladder orders must be priced within the band"
+        );
+        (price - self.min) as usize
+    }
+}
+
+/// Price levels of one side of a `LadderBook`, stored contiguously by tick offset from the
+/// bottom of the band, with a cursor on the best level.
+pub struct OrdersByTick<T: Order> {
+    side: Side,
+    band: PriceBand,
+    levels: Vec<PriceLevel<T>>,
+    nodes: Nodes<<T as Order>::Id>,
+    /// Offset of the best non-empty level.
+    best: Option<usize>,
+    quantity: <T as Order>::Amount,
+    len: usize,
+}
+
+impl<T> OrdersByTick<T>
+where
+    T: Order<Price = u64> + 'static,
+{
+    fn new(side: Side, band: PriceBand) -> Self {
+        Self {
+            side,
+            band,
+            levels: std::iter::repeat_with(Default::default)
+                .take(band.ticks())
+                .collect(),
+            nodes: Default::default(),
+            best: None,
+            quantity: Zero::zero(),
+            len: 0,
+        }
+    }
+
+    /// Returns the offsets from the given one towards the worse end of the band.
+    fn offsets_from(&self, offset: usize) -> impl Iterator<Item = usize> {
+        match self.side {
+            Side::Ask => Either::Left(offset..self.levels.len()),
+            Side::Bid => Either::Right((0..=offset).rev()),
+        }
+    }
+
+    fn is_better(&self, offset: usize, other: usize) -> bool {
+        match self.side {
+            Side::Ask => offset < other,
+            Side::Bid => offset > other,
+        }
+    }
+}
+
+impl<T> PriceLevels<T> for OrdersByTick<T>
+where
+    T: Order<Price = u64> + 'static,
+{
+    fn push_back(
+        &mut self,
+        price: u64,
+        order_id: <T as Order>::Id,
+        remaining: <T as Order>::Amount,
+    ) -> NodeHandle {
+        let offset = self.band.offset(price);
+        let level = &mut self.levels[offset];
+        let handle = self.nodes.push_back(level, order_id);
+        level.quantity = level.quantity + remaining;
+        self.quantity = self.quantity + remaining;
+        self.len += 1;
+
+        if self.best.is_none_or(|best| self.is_better(offset, best)) {
+            self.best = Some(offset);
+        }
+
+        handle
+    }
+
+    fn remove(
+        &mut self,
+        price: u64,
+        handle: NodeHandle,
+        remaining: <T as Order>::Amount,
+    ) -> <T as Order>::Id {
+        let offset = self.band.offset(price);
+        let level = &mut self.levels[offset];
+        let order_id = self.nodes.unlink(level, handle);
+        level.quantity = level.quantity - remaining;
+        self.quantity = self.quantity - remaining;
+        self.len -= 1;
+
+        // An emptied best level moves the cursor to the next non-empty level behind it.
+        if level.is_empty() && self.best == Some(offset) {
+            self.best = self
+                .offsets_from(offset)
+                .find(|&offset| !self.levels[offset].is_empty());
+        }
+
+        order_id
+    }
+
+    fn totals_mut(&mut self, price: u64) -> (&mut <T as Order>::Amount, &mut <T as Order>::Amount) {
+        let offset = self.band.offset(price);
+
+        (&mut self.levels[offset].quantity, &mut self.quantity)
+    }
+
+    fn position(&self, handle: NodeHandle) -> usize {
+        self.nodes.position(handle)
+    }
+
+    fn get(&self, price: u64) -> Option<Level<'_, T>> {
+        let level = self
+            .levels
+            .get(price.checked_sub(self.band.min)? as usize)?;
+
+        (!level.is_empty()).then(|| self.nodes.level(level))
+    }
+
+    fn levels(&self) -> impl Iterator<Item = (u64, Level<'_, T>)> + '_ {
+        self.best
+            .into_iter()
+            .flat_map(move |best| self.offsets_from(best))
+            .filter(move |&offset| !self.levels[offset].is_empty())
+            .map(move |offset| {
+                let price = self.band.min + offset as u64;
+                (price, self.nodes.level(&self.levels[offset]))
+            })
+    }
+
+    fn quantity(&self) -> <T as Order>::Amount {
+        self.quantity
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        if let Some(best) = self.best {
+            for offset in self.offsets_from(best).collect::<Vec<_>>() {
+                self.levels[offset] = Default::default();
+            }
+        }
+        self.nodes.clear();
+        self.best = None;
+        self.quantity = Zero::zero();
+        self.len = 0;
+    }
+}
+
+/// Order book for instruments with a known price band, storing the levels of each side in
+/// a contiguous array indexed by tick offset.
+pub type LadderBook = LevelBook<OrdersByTick<LimitOrder>>;
+
+impl LadderBook {
+    pub fn new(band: PriceBand) -> Self {
+        Self::with_mode(band, MatchingMode::default())
+    }
+
+    pub fn with_mode(band: PriceBand, mode: MatchingMode) -> Self {
+        Self::with_levels(
+            OrdersByTick::new(Side::Ask, band),
+            OrdersByTick::new(Side::Bid, band),
+            mode,
+        )
+    }
+
+    /// Rebuilds a book from a snapshot, queueing the orders of every level in the order
+    /// they are listed. Every order must rest within the band.
+    pub fn restore(
+        band: PriceBand,
+        mode: MatchingMode,
+        snapshot: BookSnapshot,
+    ) -> Result<Self, SnapshotError> {
+        let out_of_band = snapshot
+            .asks
+            .iter()
+            .chain(&snapshot.bids)
+            .filter(|level| !band.contains(level.price))
+            .find_map(|level| level.orders.first());
+        if let Some(order) = out_of_band {
+            return Err(SnapshotError::OutOfBand(order.id()));
+        }
+
+        Self::with_mode(band, mode).restored(snapshot)
+    }
+
+    /// Returns the prices orders may rest at.
+    pub fn band(&self) -> PriceBand {
+        self.levels(&Side::Ask).band
+    }
+}
@@ -1,7 +1,9 @@
+use num::Zero;
 use thiserror::Error;
 
-use crate::core::domain::{Match, Opposite, Order, OrderBook, Trade};
-use crate::LogTrait;
+use crate::core::domain::{Match, MatchingMode, Opposite, Order, OrderBook, Trade};
+use crate::core::order::SelfTradePrevention;
+use crate::event::EngineEvent;
 
 #[derive(Debug, Error)]
 #[non_exhaustive]
@@ -9,9 +11,20 @@ pub enum DefaultMatchingError {}
 
 pub struct MatchingEngine;
 
+impl MatchingEngine {
+    /// Returns `true` if the order would match the top of the opposite side of the book.
+    pub fn crosses<B>(book: &B, order: &<B as OrderBook>::Order) -> bool
+    where
+        B: OrderBook,
+    {
+        book.peek(&order.side().opposite())
+            .is_some_and(|top_order| top_order.matches(order).is_ok())
+    }
+}
+
 impl Match for MatchingEngine {
     type Error = DefaultMatchingError;
-    type Output = (Box<dyn LogTrait>, bool);
+    type Output = (Vec<EngineEvent>, bool);
 
     fn matching<B>(
         book: &mut B,
@@ -19,37 +32,121 @@ impl Match for MatchingEngine {
     ) -> Result<Self::Output, Self::Error>
     where
         B: OrderBook,
-        <<B as OrderBook>::Order as Order>::Acknowledgment: 'static,
     {
+        let opposite = incoming_order.side().opposite();
+        // Market, IOC and FOK orders never rest, so whatever is left unfilled is cancelled.
+        let bookable = incoming_order.is_bookable();
+        let crosses = Self::crosses(book, &incoming_order);
+
+        if book.mode() == MatchingMode::Reject {
+            return if crosses || !bookable {
+                let reject = incoming_order.ack(true);
+                Ok((vec![reject.into()], false))
+            } else {
+                let ack = incoming_order.ack(false);
+                book.place(incoming_order);
+                Ok((vec![ack.into()], true))
+            };
+        }
+
+        if !crosses && !bookable {
+            // Nothing to execute against and nowhere to rest.
+            let reject = incoming_order.ack(true);
+            return Ok((vec![reject.into()], false));
+        }
+
+        if incoming_order.is_fill_or_kill() {
+            // Orders of the same user never fill the incoming order when self-trades are
+            // prevented, and only the oldest ones are cancelled to reach past them.
+            let prevention = incoming_order.self_trade_prevention();
+            let is_self_trade = |maker: &<B as OrderBook>::Order| {
+                prevention.is_some() && maker.user_id() == incoming_order.user_id()
+            };
+            // Iceberg reserves are shown again at the back of their level, so they only count
+            // when no order that stops the matching rests in that level.
+            let mut fillable: <<B as OrderBook>::Order as Order>::Amount = Zero::zero();
+            let mut reserve = fillable;
+            let mut level_price = None;
+            for maker in book
+                .iter(&opposite)
+                .take_while(|maker| maker.matches(&incoming_order).is_ok())
+            {
+                if level_price != maker.limit_price() {
+                    level_price = maker.limit_price();
+                    fillable = fillable + reserve;
+                    reserve = Zero::zero();
+                }
+                if is_self_trade(&maker) {
+                    if prevention == Some(SelfTradePrevention::CancelOldest) {
+                        continue;
+                    }
+                    reserve = Zero::zero();
+                    break;
+                }
+                fillable = fillable + maker.displayed();
+                reserve = reserve + (maker.remaining() - maker.displayed());
+            }
+            fillable = fillable + reserve;
+
+            if fillable < incoming_order.remaining() {
+                // Not enough matching depth; reject before touching the book.
+                let reject = incoming_order.ack(true);
+                return Ok((vec![reject.into()], false));
+            }
+        }
+
+        let mut records = vec![incoming_order.ack(false).into()];
+
         while !incoming_order.is_closed() {
-            let Some(mut top_order) = book.peek_mut(&incoming_order.side().opposite()) else {
+            let Some(mut top_order) = book.peek_mut(&opposite) else {
                 // Since there is no opposite order anymore, we can move on.
                 break;
             };
 
-            let Ok(_trade) = top_order.trade(&mut incoming_order) else {
+            if let Some(prevented) = top_order.prevent_self_trade(&mut incoming_order) {
+                records.push(prevented);
+
+                if top_order.is_closed() {
+                    drop(top_order);
+                    book.pop(&opposite).expect("order should be `Some`");
+                }
+                continue;
+            }
+
+            let Ok(trade) = top_order.trade(&mut incoming_order) else {
                 // Since incoming order is not matching to top order
                 // anymore, we can also move on.
                 break;
             };
+            records.push(trade.into());
 
-            // TODO; enable trading
-            // if top_order.is_closed() {
-            //     let top_order_id = top_order.id();
-            //     // Explicit drop to reuse the order book.
-            //     drop(top_order);
-            //     // As long as top order is completed, it can be safely removed from order book.
-            //     book.cancel(&top_order_id).expect("order should be `Some`");
-            // }
+            if top_order.is_closed() {
+                // Explicit drop to reuse the order book.
+                drop(top_order);
+                // As long as top order is completed, it can be safely removed from order book.
+                book.pop(&opposite).expect("order should be `Some`");
+            } else if top_order.displayed().is_zero() {
+                drop(top_order);
+                // An iceberg whose peak is filled shows a new one from its reserve, losing its
+                // time priority to the orders behind it.
+                let mut replenished_order = book.pop(&opposite).expect("order should be `Some`");
+                replenished_order.replenish();
+                book.place(replenished_order);
+            }
         }
 
-        if incoming_order.is_closed() {
-            let reject = incoming_order.ack(true);
-            Ok((Box::new(reject), false))
-        } else {
-            let ack = incoming_order.ack(false);
-            book.place(incoming_order);
-            Ok((Box::new(ack), true))
+        // Partially filled makers are left untouched at the head of their level,
+        // so they keep their queue position; only the taker remainder is booked.
+        if !incoming_order.is_closed() {
+            if bookable {
+                // Nothing traded on entry was shown, so icebergs rest with a full peak.
+                incoming_order.replenish();
+                book.place(incoming_order);
+            } else {
+                records.push(incoming_order.cancel().into());
+            }
         }
+
+        Ok((records, true))
     }
 }
@@ -1,16 +1,22 @@
 use thiserror::Error;
+pub mod clock;
 mod depth;
 pub mod domain;
 mod engine;
+mod ladder;
 mod matcher;
 pub mod order;
 mod orderbook;
+pub mod snapshot;
 mod trade;
+mod trigger;
 
+pub use domain::{MatchingMode, OrderBook};
 pub use engine::{Engine, EngineError};
+pub use ladder::{LadderBook, PriceBand};
 pub use order::{OrderRequest, Side};
-pub use orderbook::Book;
-pub use domain::OrderBook;
+pub use orderbook::{Book, LevelBook, OrderMut, OrderUpdate};
+pub use trade::TradeImpl;
 
 #[derive(Debug, Error)]
 pub enum OrderRequestError {
@@ -18,6 +24,10 @@ pub enum OrderRequestError {
     MismatchType,
     #[error("invalid order side `{0}`")]
     InvalidOrderSide(String),
+    #[error("invalid time in force `{0}`")]
+    InvalidTimeInForce(String),
+    #[error("invalid self-trade prevention `{0}`")]
+    InvalidSelfTradePrevention(String),
 }
 
 #[derive(Debug, Error)]
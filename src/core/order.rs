@@ -1,17 +1,22 @@
 use std::borrow::Borrow;
 use std::cmp::{Ordering, Reverse};
+use std::collections::hash_map::{Entry, HashMap};
 use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 use num::Zero;
 
-use crate::Acknowledgment;
 use crate::core::{OrderError, OrderRequestError, TradeError};
 use crate::core::domain::{Opposite, Order};
 use crate::core::trade::TradeImpl;
+use crate::event::EngineEvent;
 
-#[derive(Debug)]
+/// An inbound request. JSON requests are objects tagged with a snake case `type`, e.g.
+/// `{"type":"cancel","user_id":1,"user_order_id":2}`; time in force, self-trade prevention,
+/// peak sizes, stop prices and timestamps are optional.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum OrderRequest {
     Create {
         user_id: u64,
@@ -20,19 +25,76 @@ pub enum OrderRequest {
         qty: u64,
         side: Side,
         user_order_id: u64,
-        unix_nano: u128,
+        #[serde(default)]
+        time_in_force: TimeInForce,
+        /// Overrides the self-trade prevention of the user for this order.
+        #[serde(default)]
+        self_trade_prevention: Option<SelfTradePrevention>,
+        /// Makes the order an iceberg showing at most this quantity in the book at a time.
+        #[serde(default)]
+        peak_size: Option<u64>,
+        /// Holds the order back from matching until the last trade price reaches this price.
+        #[serde(default)]
+        stop_price: Option<u64>,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        unix_nano: Option<u128>,
     },
     Cancel {
         user_id: u64,
         user_order_id: u64,
-        unix_nano: u128,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        unix_nano: Option<u128>,
     },
-    FlushBook,
+    /// Changes the price and total quantity of a resting order.
+    Amend {
+        user_id: u64,
+        user_order_id: u64,
+        price: u64,
+        qty: u64,
+        #[serde(default, deserialize_with = "deserialize_timestamp")]
+        unix_nano: Option<u128>,
+    },
+    /// Flushes the book of a single symbol, or every book when no symbol is given.
+    FlushBook {
+        #[serde(default)]
+        symbol: Option<String>,
+    },
+    /// Closes the trading session, purging every resting `Day` order.
+    EndOfSession,
+}
+
+/// Buffered, internally tagged enums cannot hold `u128` values, so JSON timestamps are read
+/// as `u64` nanoseconds, which last until the year 2554.
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<u128>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let timestamp: Option<u64> = serde::Deserialize::deserialize(deserializer)?;
+    Ok(timestamp.map(u128::from))
 }
 
-#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy)]
+/// Identifies an order; user order ids are only unique per user.
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy, Hash)]
+pub struct OrderId {
+    pub user_id: u64,
+    pub user_order_id: u64,
+}
+
+impl OrderId {
+    #[inline]
+    pub fn new(user_id: u64, user_order_id: u64) -> Self {
+        Self {
+            user_id,
+            user_order_id,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, PartialOrd, Ord, Clone, Debug, Copy, serde::Serialize, serde::Deserialize)]
 pub enum Side {
+    #[serde(rename = "S")]
     Ask,
+    #[serde(rename = "B")]
     Bid,
 }
 
@@ -68,7 +130,78 @@ impl Opposite for Side {
     }
 }
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum OrderType {
+    #[default]
+    Limit,
+    /// Executes against the opposite side at any price; never rests in the book.
+    Market,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum TimeInForce {
+    /// Good till cancelled: the remainder rests until cancelled or flushed.
+    #[default]
+    Gtc,
+    /// Immediate or cancel: the remainder is cancelled after matching.
+    Ioc,
+    /// Fill or kill: the order is filled entirely or rejected without touching the book.
+    Fok,
+    /// Rests like `Gtc` until the end of the trading session.
+    Day,
+}
+
+impl FromStr for TimeInForce {
+    type Err = OrderRequestError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "GTC" => Ok(TimeInForce::Gtc),
+            "IOC" => Ok(TimeInForce::Ioc),
+            "FOK" => Ok(TimeInForce::Fok),
+            "DAY" => Ok(TimeInForce::Day),
+            _ => Err(OrderRequestError::InvalidTimeInForce(input.to_owned())),
+        }
+    }
+}
+
+/// What happens when an incoming order would trade against a resting order of the same user.
+/// The mode of the incoming order applies; orders without one are allowed to self-trade.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum SelfTradePrevention {
+    /// The incoming order is cancelled, leaving the resting order untouched.
+    #[serde(rename = "CN")]
+    CancelNewest,
+    /// The resting order is cancelled and the incoming order keeps matching.
+    #[serde(rename = "CO")]
+    CancelOldest,
+    /// Both orders are cancelled.
+    #[serde(rename = "CB")]
+    CancelBoth,
+    /// The smaller quantity is taken off both orders, cancelling whichever is left with
+    /// nothing, without trading it.
+    #[serde(rename = "DC")]
+    DecrementAndCancel,
+}
+
+impl FromStr for SelfTradePrevention {
+    type Err = OrderRequestError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "CN" => Ok(SelfTradePrevention::CancelNewest),
+            "CO" => Ok(SelfTradePrevention::CancelOldest),
+            "CB" => Ok(SelfTradePrevention::CancelBoth),
+            "DC" => Ok(SelfTradePrevention::DecrementAndCancel),
+            _ => Err(OrderRequestError::InvalidSelfTradePrevention(
+                input.to_owned(),
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum OrderStatus {
     #[default]
     Open,
@@ -78,7 +211,7 @@ pub enum OrderStatus {
     Completed,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct LimitOrder {
     pub user_id: u64,
     pub order_id: u64,
@@ -87,9 +220,23 @@ pub struct LimitOrder {
     pub side: Side,
     pub order_symbol: String,
     pub timestamp: u128,
+    /// Engine sequence number of the request that created the order.
+    pub sequence: u64,
     pub filled: u64,
     pub status: OrderStatus,
-    // pub order_type: OrderType,
+    pub order_type: OrderType,
+    pub time_in_force: TimeInForce,
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// Quantity an iceberg order shows in the book at a time, holding the rest in reserve.
+    #[serde(default)]
+    pub peak_size: Option<u64>,
+    /// Filled quantity when the current peak of an iceberg order was shown.
+    #[serde(default)]
+    pub replenished_at: u64,
+    /// Last trade price at which a stop order is triggered.
+    #[serde(default)]
+    pub stop_price: Option<u64>,
 }
 
 impl LimitOrder {
@@ -98,6 +245,20 @@ impl LimitOrder {
             .expect("order does not have available amount to fill")
     }
 
+    /// Takes an amount off the order without filling it, cancelling the order once nothing
+    /// is left.
+    pub fn decrement(&mut self, amount: u64) {
+        assert!(
+            amount <= self.remaining(),
+            "order does not have available amount to decrement"
+        );
+        self.quantity -= amount;
+
+        if self.remaining().is_zero() {
+            self.cancel();
+        }
+    }
+
     fn try_fill(&mut self, amount: u64) -> Result<(), OrderError> {
         if amount.is_zero() {
             return Err(OrderError::NoFill);
@@ -116,6 +277,44 @@ impl LimitOrder {
     }
 }
 
+/// Returns the ids of the `Day` orders among the given ones, in id order.
+pub(crate) fn day_order_ids<'a>(orders: impl Iterator<Item = &'a LimitOrder>) -> Vec<OrderId> {
+    let mut day_order_ids: Vec<_> = orders
+        .filter(|order| order.time_in_force == TimeInForce::Day)
+        .map(Order::id)
+        .collect();
+    day_order_ids.sort_unstable();
+    day_order_ids
+}
+
+/// Number of orders held under each user order id, whatever their user, telling an order of
+/// another user apart from an unknown one without walking every order.
+#[derive(Debug, Default)]
+pub(crate) struct UserOrderIds(HashMap<u64, usize>);
+
+impl UserOrderIds {
+    pub(crate) fn insert(&mut self, order_id: &OrderId) {
+        *self.0.entry(order_id.user_order_id).or_default() += 1;
+    }
+
+    pub(crate) fn remove(&mut self, order_id: &OrderId) {
+        if let Entry::Occupied(mut count) = self.0.entry(order_id.user_order_id) {
+            *count.get_mut() -= 1;
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, user_order_id: u64) -> bool {
+        self.0.contains_key(&user_order_id)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
 impl Borrow<LimitOrder> for Reverse<LimitOrder> {
     #[inline]
     fn borrow(&self) -> &LimitOrder {
@@ -126,7 +325,7 @@ impl Borrow<LimitOrder> for Reverse<LimitOrder> {
 impl PartialEq for LimitOrder {
     #[inline]
     fn eq(&self, other: &Self) -> bool {
-        self.order_id.eq(&other.order_id)
+        self.id().eq(&other.id())
     }
 }
 impl Eq for LimitOrder {}
@@ -150,6 +349,10 @@ impl TryFrom<OrderRequest> for LimitOrder {
                 qty,
                 symbol,
                 side,
+                time_in_force,
+                self_trade_prevention,
+                peak_size,
+                stop_price,
                 unix_nano,
             } => Ok(LimitOrder {
                 user_id,
@@ -158,9 +361,21 @@ impl TryFrom<OrderRequest> for LimitOrder {
                 quantity: qty,
                 order_symbol: symbol,
                 side,
-                timestamp: unix_nano,
+                timestamp: unix_nano.unwrap_or_default(),
+                sequence: 0,
                 filled: 0,
                 status: OrderStatus::Open,
+                // Price is 0 for market order, <>0 for limit order
+                order_type: if price.is_zero() {
+                    OrderType::Market
+                } else {
+                    OrderType::Limit
+                },
+                time_in_force,
+                self_trade_prevention,
+                peak_size,
+                replenished_at: 0,
+                stop_price,
             }),
             _ => Err(OrderRequestError::MismatchType),
         }
@@ -169,17 +384,17 @@ impl TryFrom<OrderRequest> for LimitOrder {
 
 impl Order for LimitOrder {
     type Amount = u64;
-    type Id = u64;
+    type Id = OrderId;
     type UserId = u64;
     type Price = u64;
     type Side = Side;
     type OrderStatus = OrderStatus;
     type Trade = TradeImpl;
     type TradeError = TradeError;
-    type Acknowledgment = Acknowledgment;
+    type Acknowledgment = EngineEvent;
 
     fn id(&self) -> Self::Id {
-        self.order_id
+        OrderId::new(self.user_id, self.order_id)
     }
 
     fn user_id(&self) -> Self::UserId {
@@ -194,6 +409,20 @@ impl Order for LimitOrder {
         self.quantity - self.filled
     }
 
+    fn displayed(&self) -> Self::Amount {
+        match self.peak_size {
+            // Takers fill past their peak, which only applies once they rest.
+            Some(peak_size) => peak_size
+                .saturating_sub(self.filled - self.replenished_at)
+                .min(self.remaining()),
+            None => self.remaining(),
+        }
+    }
+
+    fn replenish(&mut self) {
+        self.replenished_at = self.filled;
+    }
+
     fn status(&self) -> Self::OrderStatus {
         self.status
     }
@@ -206,26 +435,53 @@ impl Order for LimitOrder {
     }
 
     fn limit_price(&self) -> Option<Self::Price> {
-        Some(self.price)
+        match self.order_type {
+            OrderType::Limit => Some(self.price),
+            OrderType::Market => None,
+        }
+    }
+
+    fn is_bookable(&self) -> bool {
+        self.order_type == OrderType::Limit
+            && matches!(self.time_in_force, TimeInForce::Gtc | TimeInForce::Day)
+    }
+
+    fn is_fill_or_kill(&self) -> bool {
+        self.time_in_force == TimeInForce::Fok
     }
 
-    fn cancel(&mut self) {
+    fn self_trade_prevention(&self) -> Option<SelfTradePrevention> {
+        self.self_trade_prevention
+    }
+
+    fn cancel(&mut self) -> Self::Acknowledgment {
         match self.status() {
             OrderStatus::Open => self.status = OrderStatus::Cancelled,
             OrderStatus::Partial => self.status = OrderStatus::Closed,
             _ => (),
         }
-    }
 
-    fn ack(&mut self, reject: bool) -> Self::Acknowledgment {
-        Acknowledgment {
-            label: if reject {
-                "R".to_string()
-            } else {
-                "A".to_string()
-            },
+        EngineEvent::Cancelled {
             user_id: self.user_id,
             user_order_id: self.order_id,
+            sequence: self.sequence,
+        }
+    }
+
+    fn ack(&mut self, reject: bool) -> Self::Acknowledgment {
+        if reject {
+            EngineEvent::Rejected {
+                user_id: self.user_id,
+                user_order_id: self.order_id,
+                reason: None,
+                sequence: self.sequence,
+            }
+        } else {
+            EngineEvent::Accepted {
+                user_id: self.user_id,
+                user_order_id: self.order_id,
+                sequence: self.sequence,
+            }
         }
     }
 }
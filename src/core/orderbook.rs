@@ -1,25 +1,137 @@
-use std::collections::btree_map::Entry;
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut};
 
 use num::Zero;
 
-use crate::core::depth::{OrdersById, OrdersBySide};
-use crate::core::domain::{Order, OrderBook, Spread, Volume};
+use crate::core::depth::{OrdersById, OrdersByPrice, OrdersBySide, PriceLevels};
+use crate::core::domain::{DepthLevel, Level, MatchingMode, Order, OrderBook, Spread, Volume};
 use crate::core::matcher::MatchingEngine;
-use crate::core::order::LimitOrder;
+use crate::core::order::{self, LimitOrder, OrderId, UserOrderIds};
+use crate::core::snapshot::{BookSnapshot, LevelSnapshot, SnapshotError};
 use crate::core::Side;
+use crate::event::OrderAction;
 
-pub struct Book {
+/// Order book keeping the price levels of each side in a level container `L`.
+pub struct LevelBook<L> {
     orders_by_id: OrdersById<LimitOrder>,
-    orders_by_side: OrdersBySide<LimitOrder>,
+    orders_by_side: OrdersBySide<L>,
+    user_order_ids: UserOrderIds,
+    mode: MatchingMode,
+    /// Changes to resting orders not taken yet, recorded only when enabled.
+    order_updates: Option<Vec<OrderUpdate>>,
+    /// Sides and prices of the levels changed since last taken, recorded only when enabled.
+    touched_levels: Option<BTreeSet<(Side, u64)>>,
+}
+
+/// Order book keeping its price levels in a tree.
+pub type Book = LevelBook<OrdersByPrice<LimitOrder>>;
+
+/// Change to a resting order, with its displayed remaining quantity and its 0-based position
+/// in the queue of its price level. Deleted orders report their last displayed quantity and
+/// position.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrderUpdate {
+    pub action: OrderAction,
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: u64,
+    pub remaining: u64,
+    pub position: usize,
+}
+
+impl OrderUpdate {
+    pub(crate) fn new(action: OrderAction, order: &LimitOrder, position: usize) -> Self {
+        Self {
+            action,
+            order_id: order.id(),
+            side: order.side,
+            price: order
+                .limit_price()
+                .expect("bookable orders must have a limit price"),
+            remaining: order.displayed(),
+            position,
+        }
+    }
+}
+
+/// Order at the head of its level borrowed for matching, which takes its fills and
+/// decrements out of the running totals of its level and side and records them once released.
+pub struct OrderMut<'e> {
+    order: &'e mut LimitOrder,
+    displayed: u64,
+    filled: u64,
+    level_quantity: &'e mut u64,
+    side_quantity: &'e mut u64,
+    order_updates: Option<&'e mut Vec<OrderUpdate>>,
+    touched_levels: Option<&'e mut BTreeSet<(Side, u64)>>,
+}
+
+impl<'e> OrderMut<'e> {
+    pub(crate) fn new(
+        order: &'e mut LimitOrder,
+        (level_quantity, side_quantity): (&'e mut u64, &'e mut u64),
+        order_updates: Option<&'e mut Vec<OrderUpdate>>,
+        touched_levels: Option<&'e mut BTreeSet<(Side, u64)>>,
+    ) -> Self {
+        Self {
+            displayed: order.displayed(),
+            filled: order.filled,
+            order,
+            level_quantity,
+            side_quantity,
+            order_updates,
+            touched_levels,
+        }
+    }
+}
+
+impl Deref for OrderMut<'_> {
+    type Target = LimitOrder;
+
+    fn deref(&self) -> &Self::Target {
+        self.order
+    }
+}
+
+impl DerefMut for OrderMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.order
+    }
+}
+
+impl Drop for OrderMut<'_> {
+    fn drop(&mut self) {
+        if self.order.displayed() == self.displayed {
+            return;
+        }
+        let taken = self.displayed - self.order.displayed();
+        *self.level_quantity -= taken;
+        *self.side_quantity -= taken;
+        if let Some(touched_levels) = self.touched_levels.as_mut() {
+            touched_levels.insert((self.order.side, self.order.price));
+        }
+
+        // Closed orders and icebergs out of their peak are popped from the book, which
+        // records their deletion.
+        if self.order.displayed().is_zero() {
+            return;
+        }
+        // Self-trade prevention decrements the order instead of filling it.
+        let action = if self.order.filled == self.filled {
+            OrderAction::Reduce
+        } else {
+            OrderAction::Fill
+        };
+        if let Some(order_updates) = self.order_updates.as_mut() {
+            order_updates.push(OrderUpdate::new(action, self.order, 0));
+        }
+    }
 }
 
 impl Default for Book {
     #[inline]
     fn default() -> Self {
-        Self {
-            orders_by_id: Default::default(),
-            orders_by_side: Default::default(),
-        }
+        Self::with_mode(Default::default())
     }
 }
 
@@ -29,17 +141,208 @@ impl Book {
         Self::default()
     }
 
+    #[inline]
+    pub fn with_mode(mode: MatchingMode) -> Self {
+        Self::with_levels(
+            OrdersByPrice::new(Side::Ask),
+            OrdersByPrice::new(Side::Bid),
+            mode,
+        )
+    }
+
+    /// Rebuilds a book from a snapshot, queueing the orders of every level in the order
+    /// they are listed.
+    pub fn restore(mode: MatchingMode, snapshot: BookSnapshot) -> Result<Self, SnapshotError> {
+        Self::with_mode(mode).restored(snapshot)
+    }
+}
+
+impl<L: PriceLevels<LimitOrder>> LevelBook<L> {
+    pub(crate) fn with_levels(asks: L, bids: L, mode: MatchingMode) -> Self {
+        Self {
+            orders_by_id: Default::default(),
+            orders_by_side: OrdersBySide::new(asks, bids),
+            user_order_ids: Default::default(),
+            mode,
+            order_updates: None,
+            touched_levels: None,
+        }
+    }
+
+    /// Returns the price levels of the given side.
+    pub(crate) fn levels(&self, side: &Side) -> &L {
+        &self.orders_by_side[side]
+    }
+
+    /// Records every change to a resting order, to be taken with `take_order_updates`.
+    pub fn with_order_updates(mut self, record: bool) -> Self {
+        self.order_updates = record.then(Vec::new);
+        self
+    }
+
+    /// Returns the changes to resting orders recorded since the last call, in the order
+    /// they happened.
+    pub fn take_order_updates(&mut self) -> Vec<OrderUpdate> {
+        self.order_updates
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Records the side and price of every level changed, to be taken with
+    /// `take_touched_levels`.
+    pub fn with_touched_levels(mut self, record: bool) -> Self {
+        self.touched_levels = record.then(BTreeSet::new);
+        self
+    }
+
+    /// Returns the sides and prices of the levels changed since the last call.
+    pub fn take_touched_levels(&mut self) -> BTreeSet<(Side, u64)> {
+        self.touched_levels
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Removes every resting order, recording their deletion from the head of each level and
+    /// the levels they left when enabled.
     pub fn flush(&mut self) {
-        self.orders_by_id.clear();
-        self.orders_by_side.flush();
+        if self.order_updates.is_none() && self.touched_levels.is_none() {
+            self.orders_by_id.clear();
+            self.user_order_ids.clear();
+            self.orders_by_side[Side::Ask].clear();
+            self.orders_by_side[Side::Bid].clear();
+            return;
+        }
+        for side in [Side::Bid, Side::Ask] {
+            while self.pop(&side).is_some() {}
+        }
+    }
+
+    /// Returns the level at the given price, with its total quantity and order count, if
+    /// any order rests there.
+    pub fn level(&self, side: &Side, price: u64) -> Option<DepthLevel<LimitOrder>> {
+        let level = self.orders_by_side[side].get(price)?;
+
+        Some((price, level.quantity(), level.len()))
+    }
+
+    /// Returns the resting order with the given id.
+    pub fn get(&self, order_id: &OrderId) -> Option<&LimitOrder> {
+        self.orders_by_id.get(order_id)
+    }
+
+    /// Returns `true` if an order of any user rests under the given user order id.
+    pub fn contains_user_order_id(&self, user_order_id: u64) -> bool {
+        self.user_order_ids.contains(user_order_id)
+    }
+
+    /// Returns every resting order, in no particular order.
+    pub fn orders(&self) -> impl Iterator<Item = &LimitOrder> {
+        self.orders_by_id.values()
+    }
+
+    /// Reduces the total quantity of a resting order in place, keeping its time priority.
+    pub fn reduce(&mut self, order_id: &OrderId, quantity: u64) -> Option<&LimitOrder> {
+        let node = self.orders_by_id.node(order_id)?;
+        let order = self.orders_by_id.get_mut(order_id)?;
+
+        assert!(
+            quantity > order.filled && quantity <= order.quantity,
+            "reduced quantity must be within the filled and current quantity"
+        );
+        let displayed = order.displayed();
+        order.quantity = quantity;
+        let reduction = displayed - order.displayed();
+
+        let price = order
+            .limit_price()
+            .expect("bookable orders must have a limit price");
+        let (level_quantity, side_quantity) = self.orders_by_side[order.side].totals_mut(price);
+        *level_quantity -= reduction;
+        *side_quantity -= reduction;
+
+        if let Some(order_updates) = self.order_updates.as_mut() {
+            let position = self.orders_by_side[order.side].position(node);
+            order_updates.push(OrderUpdate::new(OrderAction::Reduce, order, position));
+        }
+        if let Some(touched_levels) = self.touched_levels.as_mut() {
+            touched_levels.insert((order.side, price));
+        }
+
+        Some(order)
+    }
+
+    /// Captures every resting order along with its fill state and time priority.
+    pub fn snapshot(&self) -> BookSnapshot {
+        let levels = |side: Side| {
+            self.orders_by_side[side]
+                .levels()
+                .map(|(price, level)| LevelSnapshot {
+                    price,
+                    orders: level
+                        .iter()
+                        .map(|order_id| {
+                            self.orders_by_id
+                                .get(order_id)
+                                .expect("every order that lives in tree must also be in the index")
+                                .clone()
+                        })
+                        .collect(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Snapshots list levels from the lowest to the highest price.
+        BookSnapshot {
+            asks: levels(Side::Ask),
+            bids: levels(Side::Bid).into_iter().rev().collect(),
+        }
+    }
+
+    /// Queues the orders of every level of a snapshot into this empty book, in the order
+    /// they are listed.
+    pub(crate) fn restored(mut self, snapshot: BookSnapshot) -> Result<Self, SnapshotError> {
+        for (side, levels) in [(Side::Ask, snapshot.asks), (Side::Bid, snapshot.bids)] {
+            for level in levels {
+                for order in level.orders {
+                    if self.get(&order.id()).is_some() {
+                        return Err(SnapshotError::DuplicateOrder(order.id()));
+                    }
+                    if order.side != side || order.limit_price() != Some(level.price) {
+                        return Err(SnapshotError::MisplacedOrder(order.id()));
+                    }
+                    if order.is_closed() || order.displayed().is_zero() {
+                        return Err(SnapshotError::ClosedOrder(order.id()));
+                    }
+                    self.place(order);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    fn touch(&mut self, side: Side, price: u64) {
+        if let Some(touched_levels) = self.touched_levels.as_mut() {
+            touched_levels.insert((side, price));
+        }
+    }
+
+    /// Cancels every resting `Day` order, returning them in id order.
+    pub fn purge_day_orders(&mut self) -> Vec<LimitOrder> {
+        order::day_order_ids(self.orders())
+            .iter()
+            .filter_map(|order_id| self.cancel(order_id))
+            .collect()
     }
 }
 
-impl OrderBook for Book {
+impl<L: PriceLevels<LimitOrder>> OrderBook for LevelBook<L> {
     type Matching = MatchingEngine;
     type Order = LimitOrder;
     type OrderRef<'e> = &'e LimitOrder where Self: 'e;
-    type OrderRefMut<'e> = &'e mut LimitOrder where Self: 'e;
+    type OrderRefMut<'e> = OrderMut<'e> where Self: 'e;
 
     fn iter(
         &self,
@@ -51,57 +354,66 @@ impl OrderBook for Book {
                 .expect("every order in tree must also be in index")
         };
 
-        self.orders_by_side.iter(side).map(order_id_to_order)
+        self.orders_by_side[side]
+            .levels()
+            .flat_map(|(_, level)| level.iter())
+            .map(order_id_to_order)
     }
 
     fn place(&mut self, order: Self::Order) {
-        self.orders_by_side[order.side()]
-            .entry(
-                order
-                    .limit_price()
-                    .expect("bookable orders must have a limit price"),
-            )
-            .or_default()
-            .push_back(order.id());
+        // A second order under the same id would leave a ghost entry in the tree.
+        assert!(
+            !self.orders_by_id.contains_key(&order.id()),
+            "order ids must be unique within the book"
+        );
+
+        let limit_price = order
+            .limit_price()
+            .expect("bookable orders must have a limit price");
+        let levels = &mut self.orders_by_side[order.side()];
+        let node = levels.push_back(limit_price, order.id(), order.displayed());
 
-        self.orders_by_id.insert(order.id(), order);
+        if let Some(order_updates) = self.order_updates.as_mut() {
+            let position = levels
+                .get(limit_price)
+                .expect("placed orders must rest at their level")
+                .len()
+                - 1;
+            order_updates.push(OrderUpdate::new(OrderAction::Add, &order, position));
+        }
+        self.touch(order.side, limit_price);
+        self.user_order_ids.insert(&order.id());
+        self.orders_by_id.insert(order, node);
     }
 
     fn cancel(&mut self, order_id: &<Self::Order as Order>::Id) -> Option<Self::Order> {
-        let order = self.orders_by_id.remove(order_id)?;
+        let (order, node) = self.orders_by_id.remove(order_id)?;
+        self.user_order_ids.remove(order_id);
 
         let limit_price = order
             .limit_price()
             .expect("bookable orders must have a limit price");
+        let levels = &mut self.orders_by_side[order.side()];
 
-        let Entry::Occupied(mut level) = self.orders_by_side[order.side()].entry(limit_price)
-        else {
-            unreachable!("orders that lives in index must also be in the tree");
-        };
-
-        // This prevents dangling levels (level with no orders).
-        if level.get().len() == 1 {
-            level.remove().pop_front()
-        } else {
-            level
-                .get()
-                .iter()
-                .position(|&inner_order_id| order.id() == inner_order_id)
-                .and_then(|index| level.get_mut().remove(index))
+        // Finding the queue position takes a walk of the level, so it is only done when the
+        // deletion is recorded.
+        if let Some(order_updates) = self.order_updates.as_mut() {
+            let position = levels.position(node);
+            order_updates.push(OrderUpdate::new(OrderAction::Delete, &order, position));
         }
-        .expect("indexed orders must be in the book tree");
+        let unlinked_order_id = levels.remove(limit_price, node, order.displayed());
 
         assert_eq!(
-            &order.id(),
-            order_id,
+            &unlinked_order_id, order_id,
             "order id must be the same; something is wrong otherwise"
         );
+        self.touch(order.side, limit_price);
 
         order.into()
     }
 
     fn peek(&self, side: &<Self::Order as Order>::Side) -> Option<Self::OrderRef<'_>> {
-        let order_id = self.orders_by_side.peek(side)?;
+        let order_id = self.orders_by_side[side].peek()?;
 
         self.orders_by_id
             .get(order_id)
@@ -110,32 +422,44 @@ impl OrderBook for Book {
     }
 
     fn peek_mut(&mut self, side: &<Self::Order as Order>::Side) -> Option<Self::OrderRefMut<'_>> {
-        let order_id = self.orders_by_side.peek(side)?;
+        let order_id = *self.orders_by_side[side].peek()?;
 
-        self.orders_by_id
-            .get_mut(order_id)
-            .expect("every order that lives in tree must also be in the index")
-            .into()
+        let order = self
+            .orders_by_id
+            .get_mut(&order_id)
+            .expect("every order that lives in tree must also be in the index");
+        let limit_price = order
+            .limit_price()
+            .expect("bookable orders must have a limit price");
+
+        Some(OrderMut::new(
+            order,
+            self.orders_by_side[side].totals_mut(limit_price),
+            self.order_updates.as_mut(),
+            self.touched_levels.as_mut(),
+        ))
     }
 
     fn pop(&mut self, side: &<Self::Order as Order>::Side) -> Option<Self::Order> {
-        let mut level = match side {
-            side @ Side::Ask => self.orders_by_side[side].first_entry(),
-            side @ Side::Bid => self.orders_by_side[side].last_entry(),
-        }?;
-
-        let order_id = if level.get().len() == 1 {
-            // This prevents dangling levels (level with no orders).
-            level.remove().pop_front()
-        } else {
-            level.get_mut().pop_front()
-        }
-        .expect("level should always have an order");
+        let order_id = *self.orders_by_side[side].peek()?;
 
-        self.orders_by_id
+        let (order, node) = self
+            .orders_by_id
             .remove(&order_id)
-            .expect("every order that lives in tree must also be in the index")
-            .into()
+            .expect("every order that lives in tree must also be in the index");
+        self.user_order_ids.remove(&order_id);
+
+        let limit_price = order
+            .limit_price()
+            .expect("bookable orders must have a limit price");
+        self.orders_by_side[side].remove(limit_price, node, order.displayed());
+
+        if let Some(order_updates) = self.order_updates.as_mut() {
+            order_updates.push(OrderUpdate::new(OrderAction::Delete, &order, 0));
+        }
+        self.touch(*side, limit_price);
+
+        order.into()
     }
 
     fn peek_top_of_book(&self) -> Spread<Self::Order> {
@@ -153,30 +477,35 @@ impl OrderBook for Book {
         )
     }
 
+    fn best_level(&self, side: &<Self::Order as Order>::Side) -> Option<Level<Self::Order>> {
+        let (price, level) = self.orders_by_side[side].best_level()?;
+
+        Some((price, level.quantity()))
+    }
+
+    fn depth(&self, side: &<Self::Order as Order>::Side, n: usize) -> Vec<DepthLevel<Self::Order>> {
+        self.orders_by_side[side]
+            .levels()
+            .take(n)
+            .map(|(price, level)| (price, level.quantity(), level.len()))
+            .collect()
+    }
+
     fn len(&self) -> (usize, usize) {
         (
-            self.orders_by_side[Side::Ask]
-                .iter()
-                .fold(0, |acc, (_, level)| acc + level.len()),
-            self.orders_by_side[Side::Bid]
-                .iter()
-                .fold(0, |acc, (_, level)| acc + level.len()),
+            self.orders_by_side[Side::Ask].len(),
+            self.orders_by_side[Side::Bid].len(),
         )
     }
 
     fn volume(&self) -> Volume<Self::Order> {
-        let ask = self
-            .iter(&Side::Ask)
-            .map(Order::remaining)
-            .reduce(|acc, curr| acc + curr)
-            .unwrap_or_else(Zero::zero);
-
-        let bid = self
-            .iter(&Side::Bid)
-            .map(Order::remaining)
-            .reduce(|acc, curr| acc + curr)
-            .unwrap_or_else(Zero::zero);
-
-        (ask, bid)
+        (
+            self.orders_by_side[Side::Ask].quantity(),
+            self.orders_by_side[Side::Bid].quantity(),
+        )
+    }
+
+    fn mode(&self) -> MatchingMode {
+        self.mode
     }
 }
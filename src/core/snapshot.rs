@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use thiserror::Error;
+
+use crate::core::order::{LimitOrder, OrderId};
+
+/// Version written to, and the only version accepted from, snapshot files.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Error)]
+pub enum SnapshotError {
+    #[error("unsupported snapshot version {0}, expected {SNAPSHOT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("order {} of user {} is listed more than once", .0.user_order_id, .0.user_id)]
+    DuplicateOrder(OrderId),
+    #[error("order {} of user {} does not belong to its level", .0.user_order_id, .0.user_id)]
+    MisplacedOrder(OrderId),
+    #[error("order {} of user {} has nothing left to rest", .0.user_order_id, .0.user_id)]
+    ClosedOrder(OrderId),
+    #[error("order {} of user {} rests outside the price band of its book", .0.user_order_id, .0.user_id)]
+    OutOfBand(OrderId),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// State of an engine, stored as a versioned JSON document.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct EngineSnapshot {
+    pub version: u32,
+    /// Sequence number of the last request processed before the snapshot was taken.
+    pub sequence: u64,
+    pub books: BTreeMap<String, BookSnapshot>,
+    /// Stop orders of every symbol waiting to be triggered, in id order.
+    #[serde(default)]
+    pub stops: BTreeMap<String, Vec<LimitOrder>>,
+    /// Price of the last trade of every symbol that traded.
+    #[serde(default)]
+    pub last_trade_prices: BTreeMap<String, u64>,
+}
+
+impl EngineSnapshot {
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<(), SnapshotError> {
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+
+    /// Reads a snapshot, rejecting the ones written in another version.
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, SnapshotError> {
+        let snapshot: Self = serde_json::from_reader(reader)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Resting orders of a book. Levels are listed from the lowest to the highest price, and
+/// the orders of a level in their time priority.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct BookSnapshot {
+    pub asks: Vec<LevelSnapshot>,
+    pub bids: Vec<LevelSnapshot>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct LevelSnapshot {
+    pub price: u64,
+    pub orders: Vec<LimitOrder>,
+}
@@ -1,6 +1,7 @@
 use crate::core::domain::{Order, Trade};
-use crate::core::order::LimitOrder;
+use crate::core::order::{LimitOrder, SelfTradePrevention};
 use crate::core::{PriceError, Side, SideError, StatusError, TradeError};
+use crate::event::EngineEvent;
 
 impl Trade<LimitOrder> for LimitOrder {
     fn trade(&mut self, other: &mut LimitOrder) -> Result<Self::Trade, Self::TradeError> {
@@ -36,16 +37,48 @@ impl Trade<LimitOrder> for LimitOrder {
             .ok_or(PriceError::Incompatible)
             .map_err(Into::into)
     }
+
+    fn prevent_self_trade(&mut self, other: &mut LimitOrder) -> Option<EngineEvent> {
+        let (maker, taker) = (self, other);
+
+        let mode = taker.self_trade_prevention?;
+        if maker.user_id != taker.user_id || maker.matches(&*taker).is_err() {
+            return None;
+        }
+
+        let (taker_qty, maker_qty) = match mode {
+            SelfTradePrevention::CancelNewest => (taker.remaining(), 0),
+            SelfTradePrevention::CancelOldest => (0, maker.remaining()),
+            SelfTradePrevention::CancelBoth => (taker.remaining(), maker.remaining()),
+            SelfTradePrevention::DecrementAndCancel => {
+                let decremented = taker.remaining().min(maker.remaining());
+                (decremented, decremented)
+            }
+        };
+        taker.decrement(taker_qty);
+        maker.decrement(maker_qty);
+
+        Some(EngineEvent::SelfTradePrevented {
+            user_id: taker.user_id,
+            taker_order_id: taker.order_id,
+            maker_order_id: maker.order_id,
+            mode,
+            taker_qty,
+            maker_qty,
+            sequence: taker.sequence,
+        })
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
 pub struct TradeImpl {
     pub buy_user_id: u64,
     pub buy_order_id: u64,
     pub sell_user_id: u64,
     pub sell_order_id: u64,
-    pub amount: u64,
     pub price: u64,
+    pub amount: u64,
+    pub sequence: u64,
 }
 
 impl TradeImpl {
@@ -56,19 +89,26 @@ impl TradeImpl {
     ) -> Result<TradeImpl, TradeError> {
         maker.matches(&*taker)?;
 
-        let exchanged = taker.remaining().min(maker.remaining());
+        // Resting icebergs only trade their peak, whereas takers trade their whole remainder.
+        let exchanged = taker.remaining().min(maker.displayed());
         let price = maker.limit_price().expect("maker must always have a price");
 
         maker.fill(exchanged);
         taker.fill(exchanged);
 
+        let (buyer, seller) = match taker.side() {
+            Side::Bid => (&*taker, &*maker),
+            Side::Ask => (&*maker, &*taker),
+        };
+
         Ok(TradeImpl {
-            buy_user_id: taker.user_id,
-            buy_order_id: taker.id(),
-            sell_user_id: maker.user_id,
-            sell_order_id: maker.id(),
-            amount: exchanged,
+            buy_user_id: buyer.user_id,
+            buy_order_id: buyer.order_id,
+            sell_user_id: seller.user_id,
+            sell_order_id: seller.order_id,
             price,
+            amount: exchanged,
+            sequence: taker.sequence,
         })
     }
 }
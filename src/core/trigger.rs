@@ -0,0 +1,106 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::core::domain::Order;
+use crate::core::order::{self, LimitOrder, OrderId, UserOrderIds};
+use crate::core::Side;
+
+/// Stop orders of a symbol held back from matching until the last trade price reaches their
+/// stop price: rising to it for buy stops and falling to it for sell stops.
+#[derive(Default)]
+pub struct TriggerBook {
+    orders: BTreeMap<OrderId, LimitOrder>,
+    /// Buy stops by stop price, lowest first, then by sequence.
+    buy_stops: BTreeSet<(u64, u64, OrderId)>,
+    /// Sell stops by stop price, highest first, then by sequence.
+    sell_stops: BTreeSet<(Reverse<u64>, u64, OrderId)>,
+    user_order_ids: UserOrderIds,
+}
+
+impl TriggerBook {
+    /// Holds a stop order until it is triggered or cancelled.
+    pub fn insert(&mut self, order: LimitOrder) {
+        let stop_price = order
+            .stop_price
+            .expect("stop orders must have a stop price");
+        let order_id = order.id();
+        assert!(
+            !self.orders.contains_key(&order_id),
+            "order ids must be unique within the trigger book"
+        );
+
+        match order.side {
+            Side::Bid => self
+                .buy_stops
+                .insert((stop_price, order.sequence, order_id)),
+            Side::Ask => self
+                .sell_stops
+                .insert((Reverse(stop_price), order.sequence, order_id)),
+        };
+        self.user_order_ids.insert(&order_id);
+        self.orders.insert(order_id, order);
+    }
+
+    pub fn cancel(&mut self, order_id: &OrderId) -> Option<LimitOrder> {
+        let order = self.orders.remove(order_id)?;
+        self.user_order_ids.remove(order_id);
+
+        let stop_price = order
+            .stop_price
+            .expect("stop orders must have a stop price");
+        match order.side {
+            Side::Bid => self
+                .buy_stops
+                .remove(&(stop_price, order.sequence, *order_id)),
+            Side::Ask => self
+                .sell_stops
+                .remove(&(Reverse(stop_price), order.sequence, *order_id)),
+        };
+
+        Some(order)
+    }
+
+    /// Removes the next stop order triggered by the last trade price. Buy stops are
+    /// triggered before sell stops, each from the stop price reached first, and orders of
+    /// the same stop price in the order they arrived.
+    pub fn trigger(&mut self, last_trade_price: u64) -> Option<LimitOrder> {
+        let buy_stop = self
+            .buy_stops
+            .first()
+            .filter(|&&(stop_price, ..)| stop_price <= last_trade_price)
+            .map(|&(.., order_id)| order_id);
+        let sell_stop = self
+            .sell_stops
+            .first()
+            .filter(|&&(Reverse(stop_price), ..)| stop_price >= last_trade_price)
+            .map(|&(.., order_id)| order_id);
+
+        self.cancel(&buy_stop.or(sell_stop)?)
+    }
+
+    pub fn get(&self, order_id: &OrderId) -> Option<&LimitOrder> {
+        self.orders.get(order_id)
+    }
+
+    /// Returns `true` if a stop order of any user is held under the given user order id.
+    pub fn contains_user_order_id(&self, user_order_id: u64) -> bool {
+        self.user_order_ids.contains(user_order_id)
+    }
+
+    /// Returns every stop order, in id order.
+    pub fn orders(&self) -> impl Iterator<Item = &LimitOrder> {
+        self.orders.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Cancels every `Day` stop order, returning them in id order.
+    pub fn purge_day_orders(&mut self) -> Vec<LimitOrder> {
+        order::day_order_ids(self.orders())
+            .iter()
+            .filter_map(|order_id| self.cancel(order_id))
+            .collect()
+    }
+}
@@ -0,0 +1,333 @@
+use csv::StringRecord;
+
+use crate::core::order::SelfTradePrevention;
+use crate::core::{Side, TradeImpl};
+
+/// Output produced by the engine while processing requests.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EngineEvent {
+    /// A new or amended order was accepted.
+    Accepted {
+        user_id: u64,
+        user_order_id: u64,
+        sequence: u64,
+    },
+    /// A request was rejected, with the reason when there is more to it than a crossed book.
+    Rejected {
+        user_id: u64,
+        user_order_id: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        sequence: u64,
+    },
+    Trade(TradeImpl),
+    /// An incoming order would have traded against a resting order of the same user. Instead
+    /// the quantities were taken off each order, as the prevention mode of the incoming order
+    /// says; an order left with nothing is cancelled.
+    SelfTradePrevented {
+        user_id: u64,
+        taker_order_id: u64,
+        maker_order_id: u64,
+        mode: SelfTradePrevention,
+        taker_qty: u64,
+        maker_qty: u64,
+        sequence: u64,
+    },
+    /// The best price or the quantity at the best price of one side of a book changed.
+    /// `price` and `qty` are `None` once the side is eliminated.
+    TopOfBook {
+        side: Side,
+        price: Option<u64>,
+        qty: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        symbol: Option<String>,
+        sequence: u64,
+    },
+    /// A price level of one side of a book was added, changed its quantity or order count, or
+    /// was deleted. Deleted levels are published with a zero `qty` and `orders`.
+    DepthUpdate {
+        action: DepthAction,
+        side: Side,
+        price: u64,
+        qty: u64,
+        orders: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        symbol: Option<String>,
+        sequence: u64,
+    },
+    /// A resting order was added to a book, partially filled, reduced or deleted. `qty` is
+    /// the remaining quantity and `position` the 0-based place of the order in the queue of
+    /// its price level.
+    OrderUpdate {
+        action: OrderAction,
+        user_id: u64,
+        user_order_id: u64,
+        side: Side,
+        price: u64,
+        qty: u64,
+        position: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        symbol: Option<String>,
+        sequence: u64,
+    },
+    /// The last trade price reached the stop price of a stop order, which was released to be
+    /// matched.
+    Triggered {
+        user_id: u64,
+        user_order_id: u64,
+        stop_price: u64,
+        sequence: u64,
+    },
+    /// A resting order was cancelled, on request or by the end of the session.
+    Cancelled {
+        user_id: u64,
+        user_order_id: u64,
+        sequence: u64,
+    },
+    /// The book of a single symbol, or every book when no symbol is given, was flushed.
+    Flushed {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        symbol: Option<String>,
+        sequence: u64,
+    },
+    /// A request could not be processed, e.g. a malformed input record.
+    Error {
+        message: String,
+        sequence: u64,
+    },
+}
+
+impl EngineEvent {
+    /// Engine sequence number of the request that produced the event.
+    pub fn sequence(&self) -> u64 {
+        match self {
+            EngineEvent::Accepted { sequence, .. }
+            | EngineEvent::Rejected { sequence, .. }
+            | EngineEvent::SelfTradePrevented { sequence, .. }
+            | EngineEvent::TopOfBook { sequence, .. }
+            | EngineEvent::DepthUpdate { sequence, .. }
+            | EngineEvent::OrderUpdate { sequence, .. }
+            | EngineEvent::Triggered { sequence, .. }
+            | EngineEvent::Cancelled { sequence, .. }
+            | EngineEvent::Flushed { sequence, .. }
+            | EngineEvent::Error { sequence, .. } => *sequence,
+            EngineEvent::Trade(trade) => trade.sequence,
+        }
+    }
+}
+
+/// Change of a price level published by a depth update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthAction {
+    Add,
+    Change,
+    Delete,
+}
+
+/// Change of a resting order published by an order update.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderAction {
+    /// The order joined the back of its price level.
+    Add,
+    /// The order was partially filled, keeping its place at the head of its level.
+    Fill,
+    /// The order quantity was reduced by an amend, keeping its place in its level.
+    Reduce,
+    /// The order left the book, being filled, cancelled or amended to another price or size.
+    Delete,
+}
+
+impl From<TradeImpl> for EngineEvent {
+    fn from(trade: TradeImpl) -> Self {
+        EngineEvent::Trade(trade)
+    }
+}
+
+/// Renders events as records of the CSV output format:
+///
+/// ```text
+/// A, userId, userOrderId
+/// R, userId, userOrderId[, reason]
+/// T, userIdBuy, userOrderIdBuy, userIdSell, userOrderIdSell, price, quantity
+/// P, userId, takerUserOrderId, makerUserOrderId, mode (CN, CO, CB or DC), takerQuantity, makerQuantity
+/// B, side (B or S), price, totalQuantity[, symbol]
+/// L, action (A, C or D), side (B or S), price, totalQuantity, orderCount[, symbol]
+/// O, action (A, F, R or D), userId, userOrderId, side (B or S), price, remainingQuantity, queuePosition[, symbol]
+/// S, userId, userOrderId, stopPrice
+/// ```
+///
+/// Accepted orders and cancels share the `A` record, and an eliminated top of book side is
+/// published with `-` for its price and quantity.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CsvFormatter;
+
+impl CsvFormatter {
+    /// Returns the record of an event, or `None` for events without one in the CSV format,
+    /// i.e. flushes and errors.
+    pub fn format(&self, event: &EngineEvent) -> Option<StringRecord> {
+        let fields = match event {
+            EngineEvent::Accepted {
+                user_id,
+                user_order_id,
+                ..
+            }
+            | EngineEvent::Cancelled {
+                user_id,
+                user_order_id,
+                ..
+            } => vec![
+                "A".to_owned(),
+                user_id.to_string(),
+                user_order_id.to_string(),
+            ],
+            EngineEvent::Rejected {
+                user_id,
+                user_order_id,
+                reason,
+                ..
+            } => {
+                let mut fields = vec![
+                    "R".to_owned(),
+                    user_id.to_string(),
+                    user_order_id.to_string(),
+                ];
+                fields.extend(reason.clone());
+                fields
+            }
+            EngineEvent::Trade(trade) => vec![
+                "T".to_owned(),
+                trade.buy_user_id.to_string(),
+                trade.buy_order_id.to_string(),
+                trade.sell_user_id.to_string(),
+                trade.sell_order_id.to_string(),
+                trade.price.to_string(),
+                trade.amount.to_string(),
+            ],
+            EngineEvent::SelfTradePrevented {
+                user_id,
+                taker_order_id,
+                maker_order_id,
+                mode,
+                taker_qty,
+                maker_qty,
+                ..
+            } => vec![
+                "P".to_owned(),
+                user_id.to_string(),
+                taker_order_id.to_string(),
+                maker_order_id.to_string(),
+                self_trade_prevention_code(mode).to_owned(),
+                taker_qty.to_string(),
+                maker_qty.to_string(),
+            ],
+            EngineEvent::TopOfBook {
+                side,
+                price,
+                qty,
+                symbol,
+                ..
+            } => {
+                let mut fields = vec![
+                    "B".to_owned(),
+                    side_code(side).to_owned(),
+                    or_dash(price),
+                    or_dash(qty),
+                ];
+                fields.extend(symbol.clone());
+                fields
+            }
+            EngineEvent::DepthUpdate {
+                action,
+                side,
+                price,
+                qty,
+                orders,
+                symbol,
+                ..
+            } => {
+                let action = match action {
+                    DepthAction::Add => "A",
+                    DepthAction::Change => "C",
+                    DepthAction::Delete => "D",
+                };
+                let mut fields = vec![
+                    "L".to_owned(),
+                    action.to_owned(),
+                    side_code(side).to_owned(),
+                    price.to_string(),
+                    qty.to_string(),
+                    orders.to_string(),
+                ];
+                fields.extend(symbol.clone());
+                fields
+            }
+            EngineEvent::OrderUpdate {
+                action,
+                user_id,
+                user_order_id,
+                side,
+                price,
+                qty,
+                position,
+                symbol,
+                ..
+            } => {
+                let action = match action {
+                    OrderAction::Add => "A",
+                    OrderAction::Fill => "F",
+                    OrderAction::Reduce => "R",
+                    OrderAction::Delete => "D",
+                };
+                let mut fields = vec![
+                    "O".to_owned(),
+                    action.to_owned(),
+                    user_id.to_string(),
+                    user_order_id.to_string(),
+                    side_code(side).to_owned(),
+                    price.to_string(),
+                    qty.to_string(),
+                    position.to_string(),
+                ];
+                fields.extend(symbol.clone());
+                fields
+            }
+            EngineEvent::Triggered {
+                user_id,
+                user_order_id,
+                stop_price,
+                ..
+            } => vec![
+                "S".to_owned(),
+                user_id.to_string(),
+                user_order_id.to_string(),
+                stop_price.to_string(),
+            ],
+            EngineEvent::Flushed { .. } | EngineEvent::Error { .. } => return None,
+        };
+
+        Some(StringRecord::from(fields))
+    }
+}
+
+fn side_code(side: &Side) -> &'static str {
+    match side {
+        Side::Bid => "B",
+        Side::Ask => "S",
+    }
+}
+
+fn self_trade_prevention_code(mode: &SelfTradePrevention) -> &'static str {
+    match mode {
+        SelfTradePrevention::CancelNewest => "CN",
+        SelfTradePrevention::CancelOldest => "CO",
+        SelfTradePrevention::CancelBoth => "CB",
+        SelfTradePrevention::DecrementAndCancel => "DC",
+    }
+}
+
+fn or_dash(value: &Option<u64>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
@@ -0,0 +1,305 @@
+//! Append-only journal of inbound requests, written before the engine applies them, so that
+//! books can be rebuilt after a crash from the last snapshot plus the journal tail.
+//!
+//! A journal is a sequence of records, each a header of little endian `u32`s, the payload
+//! length, the CRC-32 checksum of the payload and the CRC-32 checksum of the first two, followed
+//! by the payload: the little endian `u64` engine sequence number of the request and the
+//! request as a JSON object, in the format of the JSON Lines input. Payloads are at most
+//! `MAX_RECORD_LEN` bytes.
+//!
+//! A crash while appending leaves a torn final record, cut short or failing a checksum with
+//! nothing following it. Recovery ignores it, while any other damaged record is an error. As
+//! the length is checksummed, a record cut short by the end of the journal is only ever the
+//! final one.
+
+use std::convert::TryInto;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::core::snapshot::{EngineSnapshot, SnapshotError};
+use crate::core::{Engine, EngineError, MatchingMode, OrderRequest};
+use crate::sink::NullSink;
+
+const HEADER_LEN: usize = 12;
+
+/// Largest payload a record may hold.
+pub const MAX_RECORD_LEN: usize = 1 << 20;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("journal record at byte {offset} is corrupted")]
+    Corrupted { offset: u64 },
+    #[error("journal record of {length} bytes exceeds the maximum of {MAX_RECORD_LEN}")]
+    TooLarge { length: usize },
+    #[error("journal already holds records and must be recovered before appending to it")]
+    NotRecovered,
+    #[error("journal record at byte {offset}: {source}")]
+    Json {
+        offset: u64,
+        source: serde_json::Error,
+    },
+    #[error("journal record at byte {offset} has sequence {sequence}, after {previous}")]
+    OutOfSequence {
+        offset: u64,
+        sequence: u64,
+        previous: u64,
+    },
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+    #[error(transparent)]
+    Snapshot(#[from] SnapshotError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug)]
+pub struct JournalRecord {
+    pub sequence: u64,
+    pub request: OrderRequest,
+}
+
+/// Appends records to a journal.
+pub struct Journal<W: Write> {
+    writer: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Journal<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Writes and flushes the record of a request about to be processed under `sequence`.
+    pub fn append(&mut self, sequence: u64, request: &OrderRequest) -> Result<(), JournalError> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&[0; HEADER_LEN]);
+        self.buffer.extend_from_slice(&sequence.to_le_bytes());
+        serde_json::to_writer(&mut self.buffer, request).map_err(io::Error::from)?;
+
+        let payload = &self.buffer[HEADER_LEN..];
+        if payload.len() > MAX_RECORD_LEN {
+            return Err(JournalError::TooLarge {
+                length: payload.len(),
+            });
+        }
+        let length = (payload.len() as u32).to_le_bytes();
+        let checksum = crc32fast::hash(payload).to_le_bytes();
+        self.buffer[..4].copy_from_slice(&length);
+        self.buffer[4..8].copy_from_slice(&checksum);
+        let header_checksum = crc32fast::hash(&self.buffer[..8]).to_le_bytes();
+        self.buffer[8..HEADER_LEN].copy_from_slice(&header_checksum);
+
+        self.writer.write_all(&self.buffer)?;
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl Journal<File> {
+    /// Opens the journal at `path` for appending, creating it if needed. A journal already
+    /// holding records must have been `recovered` first, as the engine would otherwise number
+    /// its requests from the start again.
+    pub fn open(path: impl AsRef<Path>, recovered: bool) -> Result<Self, JournalError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if !recovered && file.metadata()?.len() > 0 {
+            return Err(JournalError::NotRecovered);
+        }
+        Ok(Self::new(file))
+    }
+}
+
+/// Reads the records of a journal, stopping at a torn final record.
+pub struct JournalReader<R> {
+    reader: R,
+    /// Length of the journal up to the end of the last intact record.
+    valid_len: u64,
+    torn: bool,
+    failed: bool,
+}
+
+impl<R: Read> JournalReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            valid_len: 0,
+            torn: false,
+            failed: false,
+        }
+    }
+
+    /// Returns the length of the journal up to the end of the last intact record read, i.e.
+    /// where new records must be appended once a torn record was found.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    /// Returns `true` if the journal ends with a torn record.
+    pub fn is_torn(&self) -> bool {
+        self.torn
+    }
+
+    fn next_record(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        let offset = self.valid_len;
+
+        let mut header = [0; HEADER_LEN];
+        let header_len = read_full(&mut self.reader, &mut header)?;
+        if header_len == 0 {
+            return Ok(None);
+        }
+
+        if header_len < HEADER_LEN {
+            // Cut short by the end of the journal.
+            self.torn = true;
+            return Ok(None);
+        }
+
+        let length = u32::from_le_bytes(header[..4].try_into().expect("4 bytes")) as usize;
+        let checksum = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes"));
+        let header_checksum = u32::from_le_bytes(header[8..].try_into().expect("4 bytes"));
+        if crc32fast::hash(&header[..8]) != header_checksum {
+            return self.torn_or_corrupted(offset);
+        }
+        if !(8..=MAX_RECORD_LEN).contains(&length) {
+            return Err(JournalError::Corrupted { offset });
+        }
+
+        let mut payload = Vec::with_capacity(length);
+        let payload_len = (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut payload)?;
+        if payload_len < length {
+            // The length is intact, so the record was cut short by the end of the journal.
+            self.torn = true;
+            return Ok(None);
+        }
+        if crc32fast::hash(&payload) != checksum {
+            return self.torn_or_corrupted(offset);
+        }
+
+        let sequence = u64::from_le_bytes(payload[..8].try_into().expect("8 bytes"));
+        let request = serde_json::from_slice(&payload[8..])
+            .map_err(|source| JournalError::Json { offset, source })?;
+        self.valid_len += (HEADER_LEN + length) as u64;
+
+        Ok(Some(JournalRecord { sequence, request }))
+    }
+
+    /// Reports a record failing a checksum as torn if nothing follows it, as only the final
+    /// record may be torn; anything following means real damage.
+    fn torn_or_corrupted(&mut self, offset: u64) -> Result<Option<JournalRecord>, JournalError> {
+        let mut rest = [0; 1];
+        if read_full(&mut self.reader, &mut rest)? == 0 {
+            self.torn = true;
+            return Ok(None);
+        }
+        Err(JournalError::Corrupted { offset })
+    }
+}
+
+impl<R: Read> Iterator for JournalReader<R> {
+    type Item = Result<JournalRecord, JournalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        match self.next_record() {
+            Ok(record) => record.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Reads until `buffer` is full or the reader is exhausted, returning the bytes read.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(read)
+}
+
+/// Outcome of replaying a journal.
+#[derive(Debug)]
+pub struct Recovery {
+    /// State of the engine once every intact record was replayed.
+    pub snapshot: EngineSnapshot,
+    /// Number of records replayed on top of the snapshot.
+    pub replayed: usize,
+    /// Length of the journal up to the end of its last intact record.
+    pub valid_len: u64,
+    /// `true` if a torn final record was ignored.
+    pub torn: bool,
+}
+
+/// Rebuilds the state of an engine from its last snapshot, if any, and the journal. Records
+/// already covered by the snapshot are skipped and the events of the replayed ones dropped,
+/// as they were published before the crash.
+pub fn recover<R: Read>(
+    snapshot: Option<EngineSnapshot>,
+    journal: R,
+    mode: MatchingMode,
+) -> Result<Recovery, JournalError> {
+    recover_with(Engine::with_mode(NullSink, mode), snapshot, journal)
+}
+
+/// Like `recover`, replaying on an engine configured like the one that wrote the journal,
+/// e.g. with the same price bands.
+pub fn recover_with<R: Read>(
+    mut engine: Engine<NullSink>,
+    snapshot: Option<EngineSnapshot>,
+    journal: R,
+) -> Result<Recovery, JournalError> {
+    if let Some(snapshot) = snapshot {
+        engine.restore(snapshot)?;
+    }
+
+    let mut reader = JournalReader::new(journal);
+    let mut replayed = 0;
+    let mut previous = 0;
+    loop {
+        let offset = reader.valid_len();
+        let Some(record) = reader.next() else {
+            break;
+        };
+        let JournalRecord { sequence, request } = record?;
+        if sequence <= previous {
+            return Err(JournalError::OutOfSequence {
+                offset,
+                sequence,
+                previous,
+            });
+        }
+        previous = sequence;
+
+        if sequence > engine.sequence() {
+            engine.replay(sequence, request)?;
+            replayed += 1;
+        }
+    }
+
+    Ok(Recovery {
+        snapshot: engine.snapshot(),
+        replayed,
+        valid_len: reader.valid_len(),
+        torn: reader.is_torn(),
+    })
+}
@@ -1,144 +1,197 @@
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::PathBuf;
 
 use clap::Parser;
-use csv::Trim;
 use either::Either;
-use erased_serde::serialize_trait_object;
-use tap::Pipe;
 
-use crate::cli::{Config, InputType};
-use crate::core::{Engine, EngineError, OrderRequest, Side};
+use crate::cli::{ClockType, Command, Config, InputFormat, InputType, OutputFormat};
+use crate::core::clock::{InputClock, LogicalClock, WallClock};
+use crate::core::snapshot::EngineSnapshot;
+use crate::core::{Engine, EngineError, MatchingMode, OrderRequest};
+use crate::journal::{Journal, JournalError};
+use crate::parser::{JsonRequestParser, ParseError, RequestParser};
+use crate::sink::{BinarySink, CsvSink, EventSink, JsonLinesSink, NullSink};
 
+pub mod binary;
 mod cli;
 pub mod core;
+pub mod event;
+pub mod journal;
+pub mod parser;
+pub mod scenario;
+pub mod sink;
 
-pub trait LogTrait: erased_serde::Serialize + Send + Sync {
-    fn get_label(&self) -> &String;
+/// Compiles and runs the examples of the README, so that they follow the API.
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+struct ReadmeDoctests;
+
+#[derive(Debug, thiserror::Error)]
+enum ProcessingError {
+    #[error(transparent)]
+    Engine(#[from] EngineError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error(transparent)]
+    Journal(#[from] JournalError),
 }
 
-serialize_trait_object!(LogTrait);
+pub fn run() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let mut config = Config::parse();
 
-#[derive(serde::Serialize)]
-pub struct Acknowledgment {
-    pub label: String,
-    pub user_id: u64,
-    pub user_order_id: u64,
-}
+    if let Some(Command::Verify {
+        input,
+        expected,
+        trading,
+    }) = config.command.take()
+    {
+        return verify(input, expected, trading);
+    }
 
-impl LogTrait for Acknowledgment {
-    fn get_label(&self) -> &String {
-        &self.label
+    let reader = match config.input.take().unwrap_or_default() {
+        InputType::File(path) => Either::Left(std::fs::File::open(path)?),
+        InputType::Stdin => Either::Right(io::stdin()),
+    };
+
+    let writer = match config.output.take() {
+        Some(path) => Either::Left(std::fs::File::create(path)?),
+        None => Either::Right(io::stdout()),
+    };
+
+    let requests = match config.input_format {
+        InputFormat::Csv => Either::Left(RequestParser::new(reader)),
+        InputFormat::Jsonl => Either::Right(JsonRequestParser::new(reader)),
+    };
+    match config.output_format {
+        OutputFormat::Csv => run_engine(CsvSink::new(writer), &config, requests),
+        OutputFormat::Jsonl => run_engine(JsonLinesSink::new(writer), &config, requests),
+        OutputFormat::Binary => run_engine(BinarySink::new(writer), &config, requests),
     }
 }
 
-#[derive(serde::Serialize)]
-pub struct BookTop {
-    pub label: String,
-    pub side: String,
-    pub price: u64,
-    pub total_qty: u64,
-}
+fn run_engine<S: EventSink>(
+    sink: S,
+    config: &Config,
+    requests: impl Iterator<Item = Result<OrderRequest, ParseError>>,
+) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let engine = Engine::with_mode(sink, matching_mode(config.trading))
+        .with_symbol_output(config.symbols)
+        .with_depth_output(config.depth)
+        .with_order_output(config.orders);
+    let engine = with_book_config(engine, config);
+    let mut engine = match config.clock {
+        ClockType::Wall => engine.with_clock(WallClock),
+        ClockType::Logical => engine.with_clock(LogicalClock::default()),
+        ClockType::Input => engine.with_clock(InputClock::default()),
+    };
+
+    let snapshot = match &config.snapshot_in {
+        Some(path) => Some(EngineSnapshot::read(io::BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+
+    match &config.journal {
+        Some(path) if config.recover => {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            let replayer = Engine::with_mode(NullSink, matching_mode(config.trading));
+            let replayer = with_book_config(replayer, config);
+            let recovery = journal::recover_with(replayer, snapshot, io::BufReader::new(&file))?;
+            if recovery.torn {
+                eprintln!("ignoring torn final journal record");
+                // Drops the torn record so that new records follow the last intact one.
+                file.set_len(recovery.valid_len)?;
+            }
+            engine.restore(recovery.snapshot)?;
+        }
+        _ => {
+            if let Some(snapshot) = snapshot {
+                engine.restore(snapshot)?;
+            }
+        }
+    }
+
+    let journal = match &config.journal {
+        Some(path) => Some(Journal::open(path, config.recover)?),
+        None => None,
+    };
+
+    let result = process(&mut engine, journal, requests, config.strict);
+    engine.sink_mut().flush()?;
+    result?;
 
-impl LogTrait for BookTop {
-    fn get_label(&self) -> &String {
-        &self.label
+    if let Some(path) = &config.snapshot_out {
+        let mut writer = io::BufWriter::new(File::create(path)?);
+        engine.snapshot().write(&mut writer)?;
+        io::Write::flush(&mut writer)?;
     }
+    Ok(())
 }
 
-#[derive(Debug, thiserror::Error)]
-enum ProcessingError {
-    #[error(transparent)]
-    EngineError(#[from] EngineError),
-    #[error(transparent)]
-    DispatchError(#[from] std::sync::mpsc::SendError<OrderRequest>),
-    #[error(transparent)]
-    Io(#[from] io::Error),
-}
+/// Applies the options changing how books match, which a journal must be recovered with too.
+fn with_book_config<S: EventSink>(engine: Engine<S>, config: &Config) -> Engine<S> {
+    let engine = config
+        .price_band
+        .iter()
+        .fold(engine, |engine, (symbol, band)| {
+            engine.with_price_band(symbol.clone(), *band)
+        });
 
-struct InputProcessor {
-    rx: std::sync::mpsc::Receiver<OrderRequest>,
+    config
+        .self_trade_prevention
+        .iter()
+        .fold(engine, |engine, (user_id, mode)| {
+            engine.with_self_trade_prevention(*user_id, *mode)
+        })
 }
 
-impl From<InputType> for InputProcessor {
-    fn from(value: InputType) -> Self {
-        let (tx, rx) = std::sync::mpsc::channel();
-
-        std::thread::spawn(move || -> Result<(), ProcessingError> {
-            let mut rdr = match value {
-                InputType::File(path) => Either::Left(std::fs::File::open(path)?),
-                InputType::Stdin => Either::Right(io::stdin()),
-            }
-            .pipe(|either_reader| {
-                csv::ReaderBuilder::new()
-                    .trim(Trim::All)
-                    .flexible(true)
-                    .comment(Some(b'#'))
-                    .has_headers(false)
-                    .from_reader(either_reader)
-            });
-
-            for record in rdr.records().flatten() {
-                let now = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_nanos();
-                match &record[0] {
-                    "N" => {
-                        tx.send(OrderRequest::Create {
-                            user_id: record[1].parse::<u64>().unwrap(),
-                            symbol: record[2].parse().unwrap(),
-                            price: record[3].parse::<u64>().unwrap(),
-                            qty: record[4].parse::<u64>().unwrap(),
-                            side: record[5].parse::<Side>().unwrap(),
-                            user_order_id: record[6].parse::<u64>().unwrap(),
-                            unix_nano: now,
-                        })?;
-                    }
-                    "C" => {
-                        tx.send(OrderRequest::Cancel {
-                            user_id: record[1].parse::<u64>().unwrap(),
-                            user_order_id: record[2].parse::<u64>().unwrap(),
-                            unix_nano: now,
-                        })?;
-                    }
-                    "F" => {
-                        tx.send(OrderRequest::FlushBook)?;
-                    }
-                    _ => {
-                        // Skip unknown order transaction
-                    }
+fn process<S: EventSink>(
+    engine: &mut Engine<S>,
+    mut journal: Option<Journal<File>>,
+    requests: impl Iterator<Item = Result<OrderRequest, ParseError>>,
+    strict: bool,
+) -> Result<(), ProcessingError> {
+    for request in requests {
+        match request {
+            Ok(order) => {
+                // Requests are journaled before they are applied.
+                if let Some(journal) = journal.as_mut() {
+                    journal.append(engine.sequence() + 1, &order)?;
                 }
+                engine.process(order)?
             }
-
-            Ok(())
-        });
-
-        Self { rx }
+            // Strict mode aborts on the first malformed record.
+            Err(err) if strict => Err(err)?,
+            Err(err) => engine.publish_error(err.to_string())?,
+        }
     }
+    Ok(())
 }
 
-pub fn run() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
-    let mut config = Config::parse();
+fn matching_mode(trading: bool) -> MatchingMode {
+    if trading {
+        MatchingMode::Trade
+    } else {
+        MatchingMode::Reject
+    }
+}
 
-    let (log_tx, log_rx) = std::sync::mpsc::channel::<Box<dyn LogTrait>>();
+fn verify(
+    input: PathBuf,
+    expected: PathBuf,
+    trading: bool,
+) -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
+    let input = std::fs::read_to_string(input)?;
+    let expected = std::fs::read_to_string(expected)?;
+
+    let reports = scenario::verify(&input, &expected, matching_mode(trading));
+    for report in &reports {
+        println!("{}", report);
+    }
 
-    let processor = InputProcessor::from(config.input.take().unwrap_or_default());
-    std::thread::spawn(move || -> Result<(), ProcessingError> {
-        let mut engine = Engine::new(log_tx);
-        while let Ok(order) = processor.rx.recv() {
-            engine.process(order)?;
-        }
-        Ok(())
-    });
-
-    let mut csv_writer = csv::WriterBuilder::new()
-        .has_headers(false)
-        .flexible(true)
-        .from_writer(io::stdout());
-    while let Ok(record) = log_rx.recv() {
-        csv_writer.serialize(record)?;
+    let failures = reports.iter().filter(|report| report.is_failure()).count();
+    if failures > 0 {
+        Err(format!("{} of {} scenarios failed", failures, reports.len()))?;
     }
-    csv_writer.flush()?;
     Ok(())
 }
@@ -0,0 +1,241 @@
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+
+use csv::{StringRecord, Trim};
+use thiserror::Error;
+
+use crate::core::OrderRequest;
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("line {line}: missing field `{field}`")]
+    MissingField { line: u64, field: &'static str },
+    #[error("line {line}: invalid field `{field}`: `{value}`")]
+    InvalidField {
+        line: u64,
+        field: &'static str,
+        value: String,
+    },
+    #[error("line {line}: unknown record type `{value}`")]
+    UnknownRecord { line: u64, value: String },
+    #[error("line {line}: {source}")]
+    Csv { line: u64, source: csv::Error },
+    #[error("line {line}: {}", json_message(source))]
+    Json {
+        line: u64,
+        source: serde_json::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Every line is parsed on its own, so the line reported by serde is replaced by the
+/// line of the input.
+fn json_message(err: &serde_json::Error) -> String {
+    let message = err.to_string();
+    let location = format!(" at line {} column {}", err.line(), err.column());
+    match message.strip_suffix(&location) {
+        Some(message) => format!("{} at column {}", message, err.column()),
+        None => message,
+    }
+}
+
+/// Reads order requests from positional CSV records, one `Result` per record so that
+/// a malformed line never stops the records following it.
+///
+/// Records are read line by line rather than through a single `csv::Reader`, whose
+/// reported positions drift on blank lines.
+pub struct RequestParser<R> {
+    lines: Lines<R>,
+    csv_builder: csv::ReaderBuilder,
+}
+
+impl<R: io::Read> RequestParser<R> {
+    pub fn new(reader: R) -> Self {
+        let mut csv_builder = csv::ReaderBuilder::new();
+        csv_builder.trim(Trim::All).flexible(true).has_headers(false);
+
+        Self {
+            lines: Lines::new(reader),
+            csv_builder,
+        }
+    }
+
+    fn next_record(&mut self) -> Option<Result<StringRecord, ParseError>> {
+        let (line, content) = match self.lines.next_line(|content| content.starts_with(b"#"))? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err.into())),
+        };
+
+        let mut record = StringRecord::new();
+        if let Err(source) = self.csv_builder.from_reader(content).read_record(&mut record) {
+            return Some(Err(ParseError::Csv { line, source }));
+        }
+
+        let mut position = csv::Position::new();
+        position.set_line(line);
+        record.set_position(Some(position));
+
+        Some(Ok(record))
+    }
+}
+
+impl<R: io::Read> Iterator for RequestParser<R> {
+    type Item = Result<OrderRequest, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = match self.next_record()? {
+            Ok(record) => record,
+            Err(err) => return Some(Err(err)),
+        };
+
+        Some(parse_record(&record))
+    }
+}
+
+/// Reads order requests from JSON Lines, one tagged object per line as described by
+/// `OrderRequest`. Blank lines are skipped.
+pub struct JsonRequestParser<R> {
+    lines: Lines<R>,
+}
+
+impl<R: io::Read> JsonRequestParser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: Lines::new(reader),
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for JsonRequestParser<R> {
+    type Item = Result<OrderRequest, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (line, content) = match self.lines.next_line(|_| false)? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err.into())),
+        };
+
+        Some(serde_json::from_slice(content).map_err(|source| ParseError::Json { line, source }))
+    }
+}
+
+/// Reads the non-blank lines of an input, trimmed and numbered from 1.
+struct Lines<R> {
+    reader: BufReader<R>,
+    buffer: Vec<u8>,
+    line: u64,
+}
+
+impl<R: io::Read> Lines<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            buffer: Vec::new(),
+            line: 0,
+        }
+    }
+
+    /// Returns the next line that is neither blank nor matched by `skip`, with its number.
+    fn next_line(&mut self, skip: fn(&[u8]) -> bool) -> Option<io::Result<(u64, &[u8])>> {
+        loop {
+            self.buffer.clear();
+            match self.reader.read_until(b'\n', &mut self.buffer) {
+                Ok(0) => return None,
+                Ok(_) => self.line += 1,
+                Err(err) => return Some(Err(err)),
+            }
+
+            let content = self.buffer.trim_ascii();
+            if !content.is_empty() && !skip(content) {
+                break;
+            }
+        }
+
+        Some(Ok((self.line, self.buffer.trim_ascii())))
+    }
+}
+
+/// Parses a single CSV record into an order request. Requests carry a timestamp only when
+/// the record supplies the optional timestamp column. New orders take their self-trade
+/// prevention mode, iceberg peak size and stop price from the columns following it, if any.
+pub fn parse_record(record: &StringRecord) -> Result<OrderRequest, ParseError> {
+    let fields = Fields::new(record);
+
+    match fields.required::<String>(0, "record type")?.as_str() {
+        "N" => Ok(OrderRequest::Create {
+            user_id: fields.required(1, "user")?,
+            symbol: fields.required(2, "symbol")?,
+            price: fields.required(3, "price")?,
+            qty: fields.required(4, "qty")?,
+            side: fields.required(5, "side")?,
+            user_order_id: fields.required(6, "userOrderId")?,
+            time_in_force: fields.optional(7, "timeInForce")?.unwrap_or_default(),
+            self_trade_prevention: fields.optional(9, "selfTradePrevention")?,
+            peak_size: fields.optional(10, "peakSize")?,
+            stop_price: fields.optional(11, "stopPrice")?,
+            unix_nano: fields.optional(8, "timestamp")?,
+        }),
+        "C" => Ok(OrderRequest::Cancel {
+            user_id: fields.required(1, "user")?,
+            user_order_id: fields.required(2, "userOrderId")?,
+            unix_nano: fields.optional(3, "timestamp")?,
+        }),
+        "M" => Ok(OrderRequest::Amend {
+            user_id: fields.required(1, "user")?,
+            user_order_id: fields.required(2, "userOrderId")?,
+            price: fields.required(3, "newPrice")?,
+            qty: fields.required(4, "newQty")?,
+            unix_nano: fields.optional(5, "timestamp")?,
+        }),
+        "E" => Ok(OrderRequest::EndOfSession),
+        "F" => Ok(OrderRequest::FlushBook {
+            symbol: fields.optional(1, "symbol")?,
+        }),
+        value => Err(ParseError::UnknownRecord {
+            line: fields.line,
+            value: value.to_owned(),
+        }),
+    }
+}
+
+struct Fields<'r> {
+    record: &'r StringRecord,
+    line: u64,
+}
+
+impl<'r> Fields<'r> {
+    fn new(record: &'r StringRecord) -> Self {
+        Self {
+            record,
+            line: record.position().map_or(0, |position| position.line()),
+        }
+    }
+
+    fn required<T: FromStr>(&self, index: usize, field: &'static str) -> Result<T, ParseError> {
+        self.optional(index, field)?.ok_or(ParseError::MissingField {
+            line: self.line,
+            field,
+        })
+    }
+
+    /// Empty and absent trailing fields are both treated as missing.
+    fn optional<T: FromStr>(
+        &self,
+        index: usize,
+        field: &'static str,
+    ) -> Result<Option<T>, ParseError> {
+        let Some(value) = self.record.get(index).filter(|value| !value.is_empty()) else {
+            return Ok(None);
+        };
+
+        value
+            .parse()
+            .map(Some)
+            .map_err(|_| ParseError::InvalidField {
+                line: self.line,
+                field,
+                value: value.to_owned(),
+            })
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt::{Display, Formatter};
+
+use crate::core::{Engine, MatchingMode};
+use crate::parser::RequestParser;
+use crate::sink::{CsvSink, EventSink};
+
+const NAME_HEADER: &str = "#name:";
+
+/// A named block of an input or output file, introduced by a `#name:` header.
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    pub name: String,
+    /// Raw lines following the header, comments included.
+    pub lines: Vec<String>,
+}
+
+impl Scenario {
+    /// Returns the lines that are neither blank nor comments, with the whitespace around
+    /// fields removed.
+    fn records(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize_record)
+            .collect()
+    }
+}
+
+/// Splits a file into its scenarios. Anything before the first header is ignored.
+pub fn split_scenarios(text: &str) -> Vec<Scenario> {
+    let mut scenarios: Vec<Scenario> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(name) = line.trim().strip_prefix(NAME_HEADER) {
+            scenarios.push(Scenario {
+                // Headers are not consistently spaced, e.g. `scenario  1` and `scenario 1`.
+                name: name.split_whitespace().collect::<Vec<_>>().join(" "),
+                lines: Vec::new(),
+            });
+        } else if let Some(scenario) = scenarios.last_mut() {
+            scenario.lines.push(line.to_owned());
+        }
+    }
+
+    scenarios
+}
+
+fn normalize_record(line: &str) -> String {
+    line.split(',').map(str::trim).collect::<Vec<_>>().join(",")
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    /// Expected but not produced.
+    Missing(String),
+    /// Produced but not expected.
+    Unexpected(String),
+}
+
+impl Display for DiffLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DiffLine::Same(line) => write!(f, "  {}", line),
+            DiffLine::Missing(line) => write!(f, "- {}", line),
+            DiffLine::Unexpected(line) => write!(f, "+ {}", line),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(Vec<DiffLine>),
+    /// The input could not be processed.
+    Error(String),
+    /// The scenario is missing from the input or from the expected output.
+    Skipped(&'static str),
+}
+
+#[derive(Clone, Debug)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+impl ScenarioReport {
+    pub fn is_failure(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed(_) | Outcome::Error(_))
+    }
+}
+
+impl Display for ScenarioReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            Outcome::Passed => write!(f, "PASS {}", self.name),
+            Outcome::Failed(diff) => {
+                write!(f, "FAIL {}", self.name)?;
+                diff.iter().try_for_each(|line| write!(f, "\n    {}", line))
+            }
+            Outcome::Error(err) => write!(f, "FAIL {}\n    {}", self.name, err),
+            Outcome::Skipped(reason) => write!(f, "SKIP {} ({})", self.name, reason),
+        }
+    }
+}
+
+/// Runs every scenario of `input` through a fresh engine and compares its output against the
+/// scenario of the same name in `expected`. Reports follow the order of `expected`, followed by
+/// the input scenarios it lacks.
+pub fn verify(input: &str, expected: &str, mode: MatchingMode) -> Vec<ScenarioReport> {
+    let inputs = split_scenarios(input);
+    let expectations = split_scenarios(expected);
+
+    let mut reports: Vec<_> = expectations
+        .iter()
+        .map(|expected| {
+            let outcome = match inputs.iter().find(|input| input.name == expected.name) {
+                Some(input) => run_scenario(input, expected, mode),
+                None => Outcome::Skipped("no input"),
+            };
+            ScenarioReport {
+                name: expected.name.clone(),
+                outcome,
+            }
+        })
+        .collect();
+
+    reports.extend(
+        inputs
+            .iter()
+            .filter(|input| !expectations.iter().any(|expected| expected.name == input.name))
+            .map(|input| ScenarioReport {
+                name: input.name.clone(),
+                outcome: Outcome::Skipped("no expected output"),
+            }),
+    );
+
+    reports
+}
+
+fn run_scenario(input: &Scenario, expected: &Scenario, mode: MatchingMode) -> Outcome {
+    let mut engine = Engine::with_mode(Vec::new(), mode);
+
+    let text = input.lines.join("\n");
+    for request in RequestParser::new(text.as_bytes()) {
+        let result = request
+            .map_err(|err| err.to_string())
+            .and_then(|request| engine.process(request).map_err(|err| err.to_string()));
+        if let Err(err) = result {
+            return Outcome::Error(err);
+        }
+    }
+
+    let mut csv_sink = CsvSink::new(vec![]);
+    for event in engine.into_sink() {
+        if let Err(err) = csv_sink.publish(event) {
+            return Outcome::Error(err.to_string());
+        }
+    }
+    let output = match csv_sink.into_inner() {
+        Ok(output) => String::from_utf8_lossy(&output).into_owned(),
+        Err(err) => return Outcome::Error(err.to_string()),
+    };
+
+    let actual: Vec<_> = output.lines().map(normalize_record).collect();
+    let expected = expected.records();
+    if actual == expected {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(diff(&expected, &actual))
+    }
+}
+
+/// Line diff based on the longest common subsequence of both sides.
+fn diff(expected: &[String], actual: &[String]) -> Vec<DiffLine> {
+    let (n, m) = (expected.len(), actual.len());
+    // lcs[i][j] is the length of the longest common subsequence of expected[i..] and actual[j..].
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::with_capacity(n.max(m));
+    while i < n || j < m {
+        if i < n && j < m && expected[i] == actual[j] {
+            lines.push(DiffLine::Same(expected[i].clone()));
+            i += 1;
+            j += 1;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            lines.push(DiffLine::Unexpected(actual[j].clone()));
+            j += 1;
+        } else {
+            lines.push(DiffLine::Missing(expected[i].clone()));
+            i += 1;
+        }
+    }
+
+    lines
+}
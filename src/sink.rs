@@ -0,0 +1,157 @@
+use std::convert::Infallible;
+use std::io::{self, Write};
+
+use crate::binary;
+use crate::event::{CsvFormatter, EngineEvent};
+
+/// EventSink receives every event synchronously, in the order the engine produces them.
+pub trait EventSink {
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error>;
+
+    /// Flushes events buffered by the sink to their destination.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Forwards events to another thread.
+impl EventSink for std::sync::mpsc::Sender<EngineEvent> {
+    type Error = std::sync::mpsc::SendError<EngineEvent>;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error> {
+        self.send(event)
+    }
+}
+
+/// Collects events in memory, e.g. for tests.
+impl EventSink for Vec<EngineEvent> {
+    type Error = Infallible;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+/// Drops every event, e.g. while replaying requests whose events were already published.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    type Error = Infallible;
+
+    fn publish(&mut self, _event: EngineEvent) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Writes events as CSV records rendered by `CsvFormatter`. Error events are not part of the
+/// CSV output and are reported on stderr instead.
+pub struct CsvSink<W: io::Write> {
+    writer: csv::Writer<W>,
+}
+
+impl<W: io::Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_writer(writer),
+        }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(self) -> Result<W, csv::Error> {
+        self.writer
+            .into_inner()
+            .map_err(|err| io::Error::from(err.error().kind()).into())
+    }
+}
+
+impl<W: io::Write> EventSink for CsvSink<W> {
+    type Error = csv::Error;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error> {
+        match CsvFormatter.format(&event) {
+            Some(record) => self.writer.write_record(&record),
+            None => {
+                if let EngineEvent::Error { message, .. } = event {
+                    eprintln!("{}", message);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Writes events as newline-delimited JSON objects, tagged with their `event` name.
+pub struct JsonLinesSink<W: io::Write> {
+    writer: io::BufWriter<W>,
+}
+
+impl<W: io::Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: io::BufWriter::new(writer),
+        }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+impl<W: io::Write> EventSink for JsonLinesSink<W> {
+    type Error = io::Error;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error> {
+        serde_json::to_writer(&mut self.writer, &event)?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.writer.flush()
+    }
+}
+
+/// Writes events as length-prefixed binary frames, see [`crate::binary`].
+pub struct BinarySink<W: io::Write> {
+    writer: io::BufWriter<W>,
+    buffer: Vec<u8>,
+}
+
+impl<W: io::Write> BinarySink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: io::BufWriter::new(writer),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+impl<W: io::Write> EventSink for BinarySink<W> {
+    type Error = io::Error;
+
+    fn publish(&mut self, event: EngineEvent) -> Result<(), Self::Error> {
+        self.buffer.clear();
+        binary::encode(&event, &mut self.buffer);
+        self.writer.write_all(&self.buffer)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,72 @@
+use orderbook::core::order::{SelfTradePrevention, TimeInForce};
+use orderbook::core::{OrderRequest, Side};
+
+/// Fields of a create request, defaulting to a good till cancelled IBM order without any of
+/// the optional fields, so that tests only spell out the fields they care about:
+///
+/// ```ignore
+/// Create { peak_size: Some(30), ..Create::new(1, 1, 10, 100, Side::Ask) }.into()
+/// ```
+#[derive(Clone, Debug)]
+pub struct Create {
+    pub user_id: u64,
+    pub symbol: String,
+    pub price: u64,
+    pub qty: u64,
+    pub side: Side,
+    pub user_order_id: u64,
+    pub time_in_force: TimeInForce,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    pub peak_size: Option<u64>,
+    pub stop_price: Option<u64>,
+    pub unix_nano: Option<u128>,
+}
+
+impl Create {
+    pub fn new(user_id: u64, user_order_id: u64, price: u64, qty: u64, side: Side) -> Self {
+        Self {
+            user_id,
+            user_order_id,
+            price,
+            qty,
+            side,
+            ..Self::default()
+        }
+    }
+}
+
+impl Default for Create {
+    fn default() -> Self {
+        Self {
+            user_id: 1,
+            symbol: "IBM".to_string(),
+            price: 10,
+            qty: 100,
+            side: Side::Bid,
+            user_order_id: 1,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            stop_price: None,
+            unix_nano: None,
+        }
+    }
+}
+
+impl From<Create> for OrderRequest {
+    fn from(create: Create) -> Self {
+        OrderRequest::Create {
+            user_id: create.user_id,
+            symbol: create.symbol,
+            price: create.price,
+            qty: create.qty,
+            side: create.side,
+            user_order_id: create.user_order_id,
+            time_in_force: create.time_in_force,
+            self_trade_prevention: create.self_trade_prevention,
+            peak_size: create.peak_size,
+            stop_price: create.stop_price,
+            unix_nano: create.unix_nano,
+        }
+    }
+}
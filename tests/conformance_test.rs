@@ -0,0 +1,434 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use common::Create;
+use orderbook::core::domain::{DepthLevel, Level, Match, Order, Spread, Volume};
+use orderbook::core::order::{LimitOrder, OrderId, TimeInForce};
+use orderbook::core::snapshot::{BookSnapshot, LevelSnapshot};
+use orderbook::core::{
+    Book, Engine, LadderBook, MatchingMode, OrderBook, OrderRequest, OrderUpdate, PriceBand, Side,
+};
+use orderbook::event::{DepthAction, EngineEvent};
+
+/// Operations every book implementation must carry out identically.
+trait ConformingBook: OrderBook<Order = LimitOrder> {
+    fn get(&self, order_id: &OrderId) -> Option<&LimitOrder>;
+    fn reduce(&mut self, order_id: &OrderId, quantity: u64) -> bool;
+    fn take_order_updates(&mut self) -> Vec<OrderUpdate>;
+    fn snapshot(&self) -> BookSnapshot;
+    fn purge_day_orders(&mut self) -> Vec<LimitOrder>;
+}
+
+impl ConformingBook for Book {
+    fn get(&self, order_id: &OrderId) -> Option<&LimitOrder> {
+        Book::get(self, order_id)
+    }
+
+    fn reduce(&mut self, order_id: &OrderId, quantity: u64) -> bool {
+        Book::reduce(self, order_id, quantity).is_some()
+    }
+
+    fn take_order_updates(&mut self) -> Vec<OrderUpdate> {
+        Book::take_order_updates(self)
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        Book::snapshot(self)
+    }
+
+    fn purge_day_orders(&mut self) -> Vec<LimitOrder> {
+        Book::purge_day_orders(self)
+    }
+}
+
+impl ConformingBook for LadderBook {
+    fn get(&self, order_id: &OrderId) -> Option<&LimitOrder> {
+        LadderBook::get(self, order_id)
+    }
+
+    fn reduce(&mut self, order_id: &OrderId, quantity: u64) -> bool {
+        LadderBook::reduce(self, order_id, quantity).is_some()
+    }
+
+    fn take_order_updates(&mut self) -> Vec<OrderUpdate> {
+        LadderBook::take_order_updates(self)
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        LadderBook::snapshot(self)
+    }
+
+    fn purge_day_orders(&mut self) -> Vec<LimitOrder> {
+        LadderBook::purge_day_orders(self)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Op {
+    Match(LimitOrder),
+    Cancel(OrderId),
+    /// Reduces the quantity of an order by the given amount, if it has that much left.
+    Reduce(OrderId, u64),
+    PurgeDayOrders,
+}
+
+/// Everything observable about a book after an operation.
+#[derive(Debug, PartialEq)]
+struct Observation {
+    outcome: String,
+    order_updates: Vec<OrderUpdate>,
+    asks: Vec<(OrderId, u64, u64)>,
+    bids: Vec<(OrderId, u64, u64)>,
+    top: Spread<LimitOrder>,
+    best_levels: [Option<Level<LimitOrder>>; 2],
+    depth: [Vec<DepthLevel<LimitOrder>>; 2],
+    len: (usize, usize),
+    volume: Volume<LimitOrder>,
+    snapshot: String,
+}
+
+fn order(user_id: u64, price: u64, qty: u64, side: Side, time_in_force: TimeInForce) -> LimitOrder {
+    let mut order = LimitOrder::try_from(OrderRequest::from(Create {
+        time_in_force,
+        ..Create::new(user_id, user_id, price, qty, side)
+    }))
+    .unwrap();
+    order.sequence = user_id;
+    order
+}
+
+fn apply<B>(book: &mut B, op: Op) -> Observation
+where
+    B: ConformingBook,
+    <B::Matching as Match>::Output: Debug,
+    <B::Matching as Match>::Error: Debug,
+{
+    let outcome = match op {
+        Op::Match(order) => format!("{:?}", book.matching(order).unwrap()),
+        Op::Cancel(order_id) => format!("{:?}", book.cancel(&order_id).map(|order| order.id())),
+        Op::Reduce(order_id, reduction) => {
+            let quantity = book
+                .get(&order_id)
+                .filter(|order| order.remaining() > reduction)
+                .map(|order| order.quantity - reduction);
+            let reduced = quantity.map(|quantity| book.reduce(&order_id, quantity));
+            format!("{reduced:?}")
+        }
+        Op::PurgeDayOrders => {
+            let purged: Vec<_> = book.purge_day_orders().iter().map(Order::id).collect();
+            format!("{purged:?}")
+        }
+    };
+    let side = |side| -> Vec<_> {
+        book.iter(&side)
+            .map(|order| (order.id(), order.price, order.remaining()))
+            .collect()
+    };
+
+    Observation {
+        outcome,
+        asks: side(Side::Ask),
+        bids: side(Side::Bid),
+        top: book.peek_top_of_book(),
+        best_levels: [book.best_level(&Side::Ask), book.best_level(&Side::Bid)],
+        depth: [book.depth(&Side::Ask, 3), book.depth(&Side::Bid, 3)],
+        len: book.len(),
+        volume: book.volume(),
+        snapshot: serde_json::to_string(&book.snapshot()).unwrap(),
+        order_updates: book.take_order_updates(),
+    }
+}
+
+/// Applies the operations to both books, asserting they are observed identically.
+fn assert_conforming(mode: MatchingMode, band: PriceBand, ops: &[Op]) {
+    let mut book = Book::with_mode(mode).with_order_updates(true);
+    let mut ladder = LadderBook::with_mode(band, mode).with_order_updates(true);
+
+    for (index, op) in ops.iter().enumerate() {
+        let expected = apply(&mut book, op.clone());
+        let observed = apply(&mut ladder, op.clone());
+        assert_eq!(observed, expected, "operation {index}: {op:?}");
+    }
+}
+
+/// Linear congruential generator, so that generated operations are reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self, bound: u64) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) % bound
+    }
+}
+
+/// Generates a stream of orders around a price of 100, a fifth of them icebergs, cancels and
+/// reductions of earlier orders and end of session purges.
+fn generate(seed: u64, count: u64) -> Vec<Op> {
+    let mut rng = Lcg(seed);
+    let mut ops = Vec::new();
+
+    for user_id in 1..=count {
+        let earlier_user_id = rng.next(user_id) + 1;
+        let earlier = OrderId::new(earlier_user_id, earlier_user_id);
+        let op = match rng.next(20) {
+            0..=2 => Op::Cancel(earlier),
+            3 => Op::Reduce(earlier, rng.next(10) + 1),
+            4 if user_id % 50 == 0 => Op::PurgeDayOrders,
+            _ => {
+                let side = if rng.next(2) == 0 {
+                    Side::Bid
+                } else {
+                    Side::Ask
+                };
+                let price = match rng.next(25) {
+                    0 => 0,
+                    offset => 88 + offset,
+                };
+                let time_in_force = match rng.next(10) {
+                    0 => TimeInForce::Ioc,
+                    1 => TimeInForce::Fok,
+                    2 | 3 => TimeInForce::Day,
+                    _ => TimeInForce::Gtc,
+                };
+                let mut order = order(user_id, price, rng.next(50) + 1, side, time_in_force);
+                if rng.next(5) == 0 {
+                    order.peak_size = Some(rng.next(10) + 1);
+                }
+                Op::Match(order)
+            }
+        };
+        ops.push(op);
+    }
+
+    ops
+}
+
+mod valid {
+    use super::*;
+
+    fn band() -> PriceBand {
+        PriceBand::new(80, 120).unwrap()
+    }
+
+    #[test]
+    fn scripted_operations() {
+        let ops = [
+            Op::Match(order(1, 100, 10, Side::Bid, TimeInForce::Gtc)),
+            Op::Match(order(2, 100, 20, Side::Bid, TimeInForce::Day)),
+            Op::Match(order(3, 99, 30, Side::Bid, TimeInForce::Gtc)),
+            Op::Match(order(4, 102, 15, Side::Ask, TimeInForce::Gtc)),
+            Op::Match(order(5, 103, 25, Side::Ask, TimeInForce::Day)),
+            // Sweeps the best bid level and part of the next one.
+            Op::Match(order(6, 99, 35, Side::Ask, TimeInForce::Gtc)),
+            Op::Reduce(OrderId::new(3, 3), 20),
+            Op::Cancel(OrderId::new(4, 4)),
+            Op::Cancel(OrderId::new(4, 4)),
+            // Moves the best ask cursor back down.
+            Op::Match(order(7, 101, 5, Side::Ask, TimeInForce::Gtc)),
+            Op::Match(order(8, 0, 100, Side::Bid, TimeInForce::Gtc)),
+            // Rests at both edges of the band.
+            Op::Match(order(9, 80, 5, Side::Bid, TimeInForce::Day)),
+            Op::Match(order(10, 120, 5, Side::Ask, TimeInForce::Gtc)),
+            Op::PurgeDayOrders,
+        ];
+
+        assert_conforming(MatchingMode::Trade, band(), &ops);
+        assert_conforming(MatchingMode::Reject, band(), &ops);
+    }
+
+    #[test]
+    fn generated_operations() {
+        for seed in 0..10 {
+            let ops = generate(seed, 300);
+            assert_conforming(MatchingMode::Trade, band(), &ops);
+            assert_conforming(MatchingMode::Reject, band(), &ops);
+        }
+    }
+
+    #[test]
+    fn restore_snapshot() {
+        let mut book = Book::with_mode(MatchingMode::Trade);
+        for op in generate(7, 200) {
+            apply(&mut book, op);
+        }
+
+        let mut ladder = LadderBook::restore(band(), MatchingMode::Trade, book.snapshot()).unwrap();
+        for op in generate(8, 200) {
+            // Generated ids overlap with the restored orders, so only fresh ones are placed.
+            let op = match op {
+                Op::Match(mut order) => {
+                    order.user_id += 1000;
+                    order.order_id += 1000;
+                    Op::Match(order)
+                }
+                op => op,
+            };
+            assert_eq!(apply(&mut ladder, op.clone()), apply(&mut book, op));
+        }
+    }
+
+    /// Turns generated operations into engine requests, leaving out reductions.
+    fn requests() -> impl Iterator<Item = OrderRequest> {
+        generate(3, 300).into_iter().filter_map(|op| match op {
+            Op::Match(order) => Some(
+                Create {
+                    symbol: order.order_symbol,
+                    time_in_force: order.time_in_force,
+                    self_trade_prevention: order.self_trade_prevention,
+                    peak_size: order.peak_size,
+                    ..Create::new(
+                        order.user_id,
+                        order.order_id,
+                        order.price,
+                        order.quantity,
+                        order.side,
+                    )
+                }
+                .into(),
+            ),
+            Op::Cancel(order_id) => Some(OrderRequest::Cancel {
+                user_id: order_id.user_id,
+                user_order_id: order_id.user_order_id,
+                unix_nano: None,
+            }),
+            Op::Reduce(..) => None,
+            Op::PurgeDayOrders => Some(OrderRequest::EndOfSession),
+        })
+    }
+
+    #[test]
+    fn engine_with_price_band() {
+        let engine = || {
+            Engine::with_mode(Vec::new(), MatchingMode::Trade)
+                .with_depth_output(true)
+                .with_order_output(true)
+        };
+
+        let mut tree = engine();
+        let mut ladder = engine().with_price_band("IBM", band());
+        for request in requests() {
+            assert!(tree.process(request).is_ok());
+        }
+        for request in requests() {
+            assert!(ladder.process(request).is_ok());
+        }
+
+        assert_eq!(ladder.into_sink(), tree.into_sink());
+    }
+
+    #[test]
+    fn depth_updates_track_the_book() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade).with_depth_output(true);
+        for request in requests() {
+            assert!(engine.process(request).is_ok());
+        }
+
+        let mut published = BTreeMap::new();
+        for event in engine.sink() {
+            if let EngineEvent::DepthUpdate {
+                action,
+                side,
+                price,
+                qty,
+                orders,
+                ..
+            } = event
+            {
+                match action {
+                    DepthAction::Add => {
+                        assert!(published.insert((*side, *price), (*qty, *orders)).is_none())
+                    }
+                    DepthAction::Change => {
+                        assert!(published.insert((*side, *price), (*qty, *orders)).is_some())
+                    }
+                    DepthAction::Delete => assert!(published.remove(&(*side, *price)).is_some()),
+                }
+            }
+        }
+
+        let book = &engine.snapshot().books["IBM"];
+        let levels = |side, levels: &[LevelSnapshot]| {
+            levels
+                .iter()
+                .map(move |level| {
+                    let qty = level.orders.iter().map(Order::displayed).sum::<u64>();
+                    ((side, level.price), (qty, level.orders.len() as u64))
+                })
+                .collect::<Vec<_>>()
+        };
+        let expected: BTreeMap<_, _> = levels(Side::Ask, &book.asks)
+            .into_iter()
+            .chain(levels(Side::Bid, &book.bids))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(published, expected);
+    }
+}
+
+mod invalid {
+    use super::*;
+
+    #[test]
+    fn empty_price_band() {
+        assert!(PriceBand::new(10, 9).is_none());
+        assert!(PriceBand::new(0, 9).is_none());
+    }
+
+    #[test]
+    fn too_wide_price_band() {
+        assert!(PriceBand::new(1, PriceBand::MAX_TICKS).is_some());
+        assert!(PriceBand::new(1, PriceBand::MAX_TICKS + 1).is_none());
+        assert!(PriceBand::new(1, 100_000_000_000).is_none());
+    }
+
+    #[test]
+    fn reject_orders_out_of_band() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade)
+            .with_price_band("IBM", PriceBand::new(90, 110).unwrap());
+        let create = |user_id, symbol: &str, price| {
+            Create {
+                symbol: symbol.to_string(),
+                ..Create::new(user_id, user_id, price, 10, Side::Bid)
+            }
+            .into()
+        };
+
+        assert!(engine.process(create(1, "IBM", 111)).is_ok());
+        assert!(engine.process(create(2, "IBM", 110)).is_ok());
+        // Other symbols are not bound by the band.
+        assert!(engine.process(create(3, "VAL", 111)).is_ok());
+        assert!(engine
+            .process(OrderRequest::Amend {
+                user_id: 2,
+                user_order_id: 2,
+                price: 89,
+                qty: 10,
+                unix_nano: None,
+            })
+            .is_ok());
+
+        let rejections: Vec<_> = engine
+            .into_sink()
+            .into_iter()
+            .filter_map(|event| match event {
+                EngineEvent::Rejected {
+                    user_id, reason, ..
+                } => Some((user_id, reason)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            rejections,
+            [
+                (1, Some("price out of band".to_string())),
+                (2, Some("price out of band".to_string())),
+            ]
+        );
+    }
+}
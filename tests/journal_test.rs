@@ -0,0 +1,262 @@
+mod common;
+
+use common::Create;
+use orderbook::core::domain::Order;
+use orderbook::core::snapshot::EngineSnapshot;
+use orderbook::core::{Engine, MatchingMode, OrderRequest, Side};
+use orderbook::event::EngineEvent;
+use orderbook::journal::{recover, Journal, JournalError, JournalReader};
+
+fn requests() -> Vec<OrderRequest> {
+    vec![
+        Create {
+            unix_nano: Some(1711396383937299001),
+            ..Create::new(1, 1, 10, 100, Side::Bid)
+        }
+        .into(),
+        Create {
+            unix_nano: Some(1711396383937299002),
+            ..Create::new(2, 2, 11, 50, Side::Bid)
+        }
+        .into(),
+        Create {
+            unix_nano: Some(1711396383937299003),
+            ..Create::new(3, 3, 10, 120, Side::Ask)
+        }
+        .into(),
+        OrderRequest::Cancel {
+            user_id: 1,
+            user_order_id: 1,
+            unix_nano: None,
+        },
+        Create {
+            unix_nano: Some(1711396383937299004),
+            ..Create::new(4, 4, 12, 10, Side::Ask)
+        }
+        .into(),
+    ]
+}
+
+/// Processes the first `count` requests like the CLI does, journaling each one first, and
+/// returns the journal along with the snapshot of the engine once it processed
+/// `snapshot_after` requests.
+fn journaled_run(
+    count: usize,
+    snapshot_after: usize,
+) -> (Vec<u8>, EngineSnapshot, Engine<Vec<EngineEvent>>) {
+    let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+    let mut journal = Journal::new(Vec::new());
+    let mut snapshot = engine.snapshot();
+
+    for (index, request) in requests().into_iter().take(count).enumerate() {
+        if index == snapshot_after {
+            snapshot = engine.snapshot();
+        }
+        // A malformed record in between consumes a sequence number without being journaled.
+        if index == 2 {
+            assert!(engine
+                .publish_error("line 3: unknown record type `X`")
+                .is_ok());
+        }
+        journal.append(engine.sequence() + 1, &request).unwrap();
+        assert!(engine.process(request).is_ok());
+    }
+
+    (journal.into_inner(), snapshot, engine)
+}
+
+/// Asserts that both snapshots hold the same orders in the same queues. Logical timestamps
+/// are left out, as the clock of a recovered engine starts over.
+fn assert_same_state(recovered: &EngineSnapshot, expected: &EngineSnapshot) {
+    let queues = |snapshot: &EngineSnapshot| -> Vec<_> {
+        snapshot
+            .books
+            .values()
+            .flat_map(|book| book.asks.iter().chain(&book.bids))
+            .map(|level| {
+                let orders: Vec<_> = level
+                    .orders
+                    .iter()
+                    .map(|order| (order.id(), order.quantity, order.filled, order.sequence))
+                    .collect();
+                (level.price, orders)
+            })
+            .collect()
+    };
+
+    assert_eq!(recovered.sequence, expected.sequence);
+    assert_eq!(queues(recovered), queues(expected));
+}
+
+mod valid {
+    use super::*;
+
+    #[test]
+    fn read_records() {
+        let (journal, _, _) = journaled_run(5, 0);
+
+        let records: Vec<_> = JournalReader::new(journal.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let sequences: Vec<_> = records.iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, [1, 2, 4, 5, 6]);
+        assert!(matches!(
+            records[2].request,
+            OrderRequest::Create {
+                user_id: 3,
+                price: 10,
+                qty: 120,
+                side: Side::Ask,
+                unix_nano: Some(1711396383937299003),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn recover_from_journal() {
+        let (journal, _, engine) = journaled_run(5, 0);
+
+        let recovery = recover(None, journal.as_slice(), MatchingMode::Trade).unwrap();
+        assert_eq!(recovery.replayed, 5);
+        assert!(!recovery.torn);
+        assert_eq!(recovery.valid_len, journal.len() as u64);
+        assert_same_state(&recovery.snapshot, &engine.snapshot());
+    }
+
+    #[test]
+    fn recover_from_snapshot_and_journal_tail() {
+        let (journal, snapshot, engine) = journaled_run(5, 3);
+
+        let recovery = recover(Some(snapshot), journal.as_slice(), MatchingMode::Trade).unwrap();
+        assert_eq!(recovery.replayed, 2);
+        assert_same_state(&recovery.snapshot, &engine.snapshot());
+    }
+
+    #[test]
+    fn ignore_torn_final_record() {
+        let (mut journal, _, _) = journaled_run(5, 0);
+        let (intact, _, engine) = journaled_run(4, 0);
+
+        // Cut short within the final record.
+        journal.truncate(journal.len() - 3);
+        let recovery = recover(None, journal.as_slice(), MatchingMode::Trade).unwrap();
+        assert!(recovery.torn);
+        assert_eq!(recovery.replayed, 4);
+        assert_eq!(recovery.valid_len, intact.len() as u64);
+        assert_same_state(&recovery.snapshot, &engine.snapshot());
+
+        // Complete, but failing its checksum.
+        let (mut journal, _, _) = journaled_run(5, 0);
+        let last = journal.len() - 1;
+        journal[last] ^= 0xff;
+        let recovery = recover(None, journal.as_slice(), MatchingMode::Trade).unwrap();
+        assert!(recovery.torn);
+        assert_eq!(recovery.valid_len, intact.len() as u64);
+        assert_same_state(&recovery.snapshot, &engine.snapshot());
+    }
+
+    #[test]
+    fn append_after_recovery() {
+        let path = std::env::temp_dir().join(format!("journal-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut first_run = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+        let mut journal = Journal::open(&path, false).unwrap();
+        for request in requests().into_iter().take(3) {
+            journal.append(first_run.sequence() + 1, &request).unwrap();
+            assert!(first_run.process(request).is_ok());
+        }
+        drop(journal);
+
+        // Numbering the requests of a second run from the start would break the journal.
+        assert!(matches!(
+            Journal::open(&path, false),
+            Err(JournalError::NotRecovered)
+        ));
+
+        let journal = std::fs::read(&path).unwrap();
+        let recovery = recover(None, journal.as_slice(), MatchingMode::Trade).unwrap();
+        let mut second_run = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+        assert!(second_run.restore(recovery.snapshot).is_ok());
+        let mut journal = Journal::open(&path, true).unwrap();
+        for request in requests().into_iter().skip(3) {
+            journal.append(second_run.sequence() + 1, &request).unwrap();
+            assert!(second_run.process(request).is_ok());
+        }
+        drop(journal);
+
+        let journal = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let recovery = recover(None, journal.as_slice(), MatchingMode::Trade).unwrap();
+        assert_eq!(recovery.replayed, 5);
+        assert_same_state(&recovery.snapshot, &second_run.snapshot());
+    }
+}
+
+mod invalid {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn corrupted_record() {
+        let (mut journal, _, _) = journaled_run(5, 0);
+        // The checksum of the first record.
+        journal[4] ^= 0xff;
+
+        assert!(matches!(
+            recover(None, journal.as_slice(), MatchingMode::Trade),
+            Err(JournalError::Corrupted { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn corrupted_length() {
+        let (mut journal, _, _) = journaled_run(5, 0);
+        let first_len = u32::from_le_bytes(journal[..4].try_into().unwrap()) as usize;
+        let second = 12 + first_len;
+        // A longer length of the second record would otherwise read up to the end of the
+        // journal, as if it were torn.
+        journal[second + 1] ^= 0x04;
+
+        assert!(matches!(
+            recover(None, journal.as_slice(), MatchingMode::Trade),
+            Err(JournalError::Corrupted { offset }) if offset == second as u64
+        ));
+    }
+
+    #[test]
+    fn out_of_sequence_record() {
+        let mut journal = Journal::new(Vec::new());
+        journal
+            .append(
+                2,
+                &Create {
+                    unix_nano: Some(1711396383937299001),
+                    ..Create::new(1, 1, 10, 100, Side::Bid)
+                }
+                .into(),
+            )
+            .unwrap();
+        journal
+            .append(
+                2,
+                &Create {
+                    unix_nano: Some(1711396383937299002),
+                    ..Create::new(2, 2, 10, 100, Side::Bid)
+                }
+                .into(),
+            )
+            .unwrap();
+        let journal = journal.into_inner();
+
+        assert!(matches!(
+            recover(None, journal.as_slice(), MatchingMode::Trade),
+            Err(JournalError::OutOfSequence {
+                sequence: 2,
+                previous: 2,
+                ..
+            })
+        ));
+    }
+}
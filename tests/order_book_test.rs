@@ -1,9 +1,46 @@
-use orderbook::core::{Book, OrderBook, Side};
+mod common;
+
+use common::Create;
+use orderbook::core::{Book, Engine, MatchingMode, OrderBook, OrderRequest, Side};
+use orderbook::event::{EngineEvent, OrderAction};
+use orderbook::sink::{CsvSink, EventSink};
 use tap::Tap;
 
+fn render(events: Vec<EngineEvent>) -> Vec<String> {
+    let mut sink = CsvSink::new(vec![]);
+    for event in events {
+        sink.publish(event).unwrap();
+    }
+    String::from_utf8(sink.into_inner().unwrap())
+        .unwrap()
+        .lines()
+        .map(str::to_owned)
+        .collect()
+}
+
+fn label(event: &EngineEvent) -> &'static str {
+    match event {
+        EngineEvent::Accepted { .. } | EngineEvent::Cancelled { .. } => "A",
+        EngineEvent::Rejected { .. } => "R",
+        EngineEvent::Trade(_) => "T",
+        EngineEvent::SelfTradePrevented { .. } => "P",
+        EngineEvent::TopOfBook { .. } => "B",
+        EngineEvent::DepthUpdate { .. } => "L",
+        EngineEvent::OrderUpdate { .. } => "O",
+        EngineEvent::Triggered { .. } => "S",
+        EngineEvent::Flushed { .. } => "F",
+        EngineEvent::Error { .. } => "E",
+    }
+}
+
 mod valid {
     use super::*;
-    use orderbook::core::order::LimitOrder;
+    use orderbook::core::clock::{Clock, InputClock, LogicalClock};
+    use orderbook::core::domain::Order;
+    use orderbook::core::order::{
+        LimitOrder, OrderId, OrderType, SelfTradePrevention, TimeInForce,
+    };
+    use std::convert::TryFrom;
     #[test]
     fn generate_reject() {
         let mut orderbook = Book::new().tap_mut(|orderbook| {
@@ -15,8 +52,15 @@ mod valid {
                 side: Side::Bid,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937299000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -28,8 +72,15 @@ mod valid {
                 side: Side::Ask,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937305000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -41,8 +92,15 @@ mod valid {
                 side: Side::Bid,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937306000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -54,8 +112,15 @@ mod valid {
                 side: Side::Ask,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937307000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
         });
@@ -68,8 +133,15 @@ mod valid {
             side: Side::Bid,
             order_symbol: "IBM".to_string(),
             timestamp: 1711396383937308000,
+            sequence: 0,
             filled: 0,
             status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
         };
 
         let first_reject = orderbook.matching(first_rejected_limit_order);
@@ -84,8 +156,15 @@ mod valid {
             side: Side::Ask,
             order_symbol: "IBM".to_string(),
             timestamp: 1711396383937309000,
+            sequence: 0,
             filled: 0,
             status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
         };
 
         let second_reject = orderbook.matching(second_rejected_limit_order);
@@ -104,8 +183,15 @@ mod valid {
                 side: Side::Bid,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937299000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -117,8 +203,15 @@ mod valid {
                 side: Side::Ask,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937305000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -130,8 +223,15 @@ mod valid {
                 side: Side::Bid,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937306000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
 
@@ -143,19 +243,1511 @@ mod valid {
                 side: Side::Ask,
                 order_symbol: "IBM".to_string(),
                 timestamp: 1711396383937307000,
+                sequence: 0,
                 filled: 0,
                 status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
             };
             assert!(orderbook.matching(limit_order).is_ok());
         });
 
-        let order_id: u64 = 2;
+        let order_id = OrderId::new(1, 2);
         let result = orderbook.cancel(&order_id);
         assert!(result.is_some());
         assert_eq!(result.unwrap().order_id, 2);
 
         // order id doesn't exist
-        let nonexistent_order_id = 5;
+        let nonexistent_order_id = OrderId::new(1, 5);
         assert!(orderbook.cancel(&nonexistent_order_id).is_none());
     }
+
+    #[test]
+    fn execute_trades() {
+        let mut orderbook = Book::with_mode(MatchingMode::Trade).tap_mut(|orderbook| {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 1,
+                price: 10,
+                quantity: 100,
+                side: Side::Bid,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937299000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 2,
+                price: 10,
+                quantity: 50,
+                side: Side::Bid,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937305000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        });
+
+        let crossing_limit_order = LimitOrder {
+            user_id: 2,
+            order_id: 101,
+            price: 10,
+            quantity: 120,
+            side: Side::Ask,
+            order_symbol: "IBM".to_string(),
+            timestamp: 1711396383937306000,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+
+        let (records, accepted) = orderbook.matching(crossing_limit_order).unwrap();
+        assert!(accepted);
+        let labels: Vec<_> = records.iter().map(label).collect();
+        assert_eq!(labels, ["A", "T", "T"]);
+
+        // The fully filled maker is removed, the partially filled one keeps its place.
+        assert_eq!(orderbook.len(), (0, 1));
+        let top_bid = orderbook.peek(&Side::Bid).unwrap();
+        assert_eq!(top_bid.order_id, 2);
+        assert_eq!(top_bid.filled, 20);
+    }
+
+    #[test]
+    fn route_orders_by_symbol() {
+        let mut engine = Engine::new(Vec::new());
+
+        let requests = [
+            Create {
+                unix_nano: Some(1711396383937299000),
+                ..Create::new(1, 1, 10, 100, Side::Bid)
+            }
+            .into(),
+            // Would cross the IBM bid, but lives in its own book.
+            Create {
+                symbol: "VAL".to_string(),
+                unix_nano: Some(1711396383937305000),
+                ..Create::new(2, 101, 9, 100, Side::Ask)
+            }
+            .into(),
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937306000),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        let labels: Vec<_> = engine.into_sink().iter().map(label).collect();
+        assert_eq!(labels, ["A", "B", "A", "B", "A", "B"]);
+    }
+
+    #[test]
+    fn market_order_sweeps_levels() {
+        let mut orderbook = Book::with_mode(MatchingMode::Trade).tap_mut(|orderbook| {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 1,
+                price: 11,
+                quantity: 100,
+                side: Side::Ask,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937299000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 2,
+                price: 12,
+                quantity: 100,
+                side: Side::Ask,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937305000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        });
+
+        let market_order = LimitOrder {
+            user_id: 2,
+            order_id: 101,
+            price: 0,
+            quantity: 250,
+            side: Side::Bid,
+            order_symbol: "IBM".to_string(),
+            timestamp: 1711396383937306000,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: OrderType::Market,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+
+        let (records, accepted) = orderbook.matching(market_order).unwrap();
+        assert!(accepted);
+        assert_eq!(records.len(), 4);
+
+        // The unfilled remainder is cancelled rather than rested.
+        assert!(matches!(
+            records.last(),
+            Some(EngineEvent::Cancelled {
+                user_id: 2,
+                user_order_id: 101,
+                ..
+            })
+        ));
+        assert!(orderbook.is_empty());
+
+        // With no liquidity left, a market order is rejected outright.
+        let market_order = LimitOrder {
+            user_id: 2,
+            order_id: 102,
+            price: 0,
+            quantity: 100,
+            side: Side::Bid,
+            order_symbol: "IBM".to_string(),
+            timestamp: 1711396383937307000,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: OrderType::Market,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+        assert!(!orderbook.matching(market_order).unwrap().1);
+    }
+
+    #[test]
+    fn immediate_time_in_force() {
+        let mut orderbook = Book::with_mode(MatchingMode::Trade).tap_mut(|orderbook| {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 1,
+                price: 11,
+                quantity: 100,
+                side: Side::Ask,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937299000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        });
+
+        // Not enough depth to fill entirely, so the book must be left untouched.
+        let fill_or_kill_order = LimitOrder {
+            user_id: 2,
+            order_id: 101,
+            price: 11,
+            quantity: 150,
+            side: Side::Bid,
+            order_symbol: "IBM".to_string(),
+            timestamp: 1711396383937305000,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: TimeInForce::Fok,
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+        assert!(!orderbook.matching(fill_or_kill_order).unwrap().1);
+        assert_eq!(orderbook.peek(&Side::Ask).unwrap().filled, 0);
+
+        // Fills what it can and cancels the remainder instead of resting it.
+        let immediate_or_cancel_order = LimitOrder {
+            user_id: 2,
+            order_id: 102,
+            price: 11,
+            quantity: 150,
+            side: Side::Bid,
+            order_symbol: "IBM".to_string(),
+            timestamp: 1711396383937306000,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: TimeInForce::Ioc,
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+        let (records, accepted) = orderbook.matching(immediate_or_cancel_order).unwrap();
+        assert!(accepted);
+        assert_eq!(records.len(), 3);
+        assert!(matches!(
+            records.last(),
+            Some(EngineEvent::Cancelled {
+                user_id: 2,
+                user_order_id: 102,
+                ..
+            })
+        ));
+        assert!(orderbook.is_empty());
+    }
+
+    #[test]
+    fn purge_day_orders() {
+        let mut orderbook = Book::new().tap_mut(|orderbook| {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 1,
+                price: 10,
+                quantity: 100,
+                side: Side::Bid,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937299000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: TimeInForce::Day,
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 2,
+                price: 12,
+                quantity: 100,
+                side: Side::Ask,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937305000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: TimeInForce::Gtc,
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        });
+
+        let purged_orders = orderbook.purge_day_orders();
+        assert_eq!(purged_orders.len(), 1);
+        assert_eq!(purged_orders[0].order_id, 1);
+        assert_eq!(orderbook.len(), (1, 0));
+    }
+
+    #[test]
+    fn amend_order() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create {
+                unix_nano: Some(1711396383937299000),
+                ..Create::new(1, 1, 10, 100, Side::Bid)
+            }
+            .into(),
+            Create {
+                unix_nano: Some(1711396383937305000),
+                ..Create::new(2, 101, 10, 100, Side::Bid)
+            }
+            .into(),
+            // Size decrease keeps the order ahead of 101.
+            OrderRequest::Amend {
+                user_id: 1,
+                user_order_id: 1,
+                price: 10,
+                qty: 50,
+                unix_nano: Some(1711396383937306000),
+            },
+            Create {
+                unix_nano: Some(1711396383937307000),
+                ..Create::new(2, 102, 11, 50, Side::Ask)
+            }
+            .into(),
+            // Price change re-queues the order and makes it trade.
+            OrderRequest::Amend {
+                user_id: 2,
+                user_order_id: 102,
+                price: 10,
+                qty: 50,
+                unix_nano: Some(1711396383937308000),
+            },
+            // Unknown order is rejected.
+            OrderRequest::Amend {
+                user_id: 2,
+                user_order_id: 103,
+                price: 10,
+                qty: 50,
+                unix_nano: Some(1711396383937309000),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,B,10,100",
+                "A,2,101",
+                "B,B,10,200",
+                "A,1,1",
+                "B,B,10,150",
+                "A,2,102",
+                "B,S,11,50",
+                "A,2,102",
+                "T,1,1,2,102,10,50",
+                "B,B,10,100",
+                "B,S,-,-",
+                "R,2,103,unknown order",
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_duplicate_order_id() {
+        let mut engine = Engine::new(Vec::new());
+
+        let requests = [
+            Create {
+                unix_nano: Some(1711396383937299000),
+                ..Create::new(1, 1, 10, 100, Side::Bid)
+            }
+            .into(),
+            // Same user order id from another user is a distinct order.
+            Create {
+                unix_nano: Some(1711396383937305000),
+                ..Create::new(2, 1, 9, 100, Side::Bid)
+            }
+            .into(),
+            // Duplicates are rejected even when routed to another symbol.
+            Create {
+                symbol: "VAL".to_string(),
+                unix_nano: Some(1711396383937306000),
+                ..Create::new(1, 1, 12, 100, Side::Ask)
+            }
+            .into(),
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937307000),
+            },
+            OrderRequest::Cancel {
+                user_id: 2,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937308000),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        let acks: Vec<_> = engine
+            .into_sink()
+            .iter()
+            .map(label)
+            .filter(|label| *label != "B")
+            .collect();
+        assert_eq!(acks, ["A", "A", "R", "A", "A"]);
+    }
+
+    #[test]
+    fn cancel_requires_ownership() {
+        let mut orderbook = Book::new().tap_mut(|orderbook| {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id: 1,
+                price: 10,
+                quantity: 100,
+                side: Side::Bid,
+                order_symbol: "IBM".to_string(),
+                timestamp: 1711396383937299000,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        });
+
+        // Another user sharing the numeric order id cannot reach the order.
+        assert!(orderbook.cancel(&OrderId::new(2, 1)).is_none());
+        assert_eq!(orderbook.len(), (0, 1));
+        assert!(orderbook.cancel(&OrderId::new(1, 1)).is_some());
+    }
+
+    #[test]
+    fn reject_cross_user_cancel() {
+        let mut engine = Engine::new(Vec::new());
+
+        let requests = [
+            Create {
+                unix_nano: Some(1711396383937299000),
+                ..Create::new(1, 1, 10, 100, Side::Bid)
+            }
+            .into(),
+            OrderRequest::Cancel {
+                user_id: 2,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937305000),
+            },
+            OrderRequest::Cancel {
+                user_id: 2,
+                user_order_id: 5,
+                unix_nano: Some(1711396383937306000),
+            },
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937307000),
+            },
+            // Already cancelled by its owner.
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937308000),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,B,10,100",
+                "R,2,1,not order owner",
+                "R,2,5,unknown order",
+                "A,1,1",
+                "B,B,-,-",
+                "R,1,1,unknown order",
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_cross_user_cancel_of_stop_and_filled_orders() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create {
+                stop_price: Some(20),
+                ..Create::new(1, 1, 0, 10, Side::Bid)
+            }
+            .into(),
+            Create::new(1, 2, 10, 10, Side::Bid).into(),
+            // Fills the second order, leaving no order under its id.
+            Create::new(2, 3, 10, 10, Side::Ask).into(),
+            OrderRequest::Cancel {
+                user_id: 3,
+                user_order_id: 1,
+                unix_nano: None,
+            },
+            OrderRequest::Cancel {
+                user_id: 3,
+                user_order_id: 2,
+                unix_nano: None,
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()).split_off(6),
+            ["R,3,1,not order owner", "R,3,2,unknown order"]
+        );
+    }
+
+    #[test]
+    fn stamp_sequence_numbers() {
+        let mut engine = Engine::new(Vec::new()).with_clock(InputClock::default());
+
+        let requests = [
+            Create {
+                unix_nano: Some(1711396383937299000),
+                ..Create::new(1, 1, 10, 100, Side::Bid)
+            }
+            .into(),
+            Create::new(1, 2, 12, 100, Side::Ask).into(),
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: Some(1711396383937305000),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+        assert_eq!(engine.sequence(), 3);
+
+        let sequences: Vec<_> = engine
+            .into_sink()
+            .iter()
+            .map(EngineEvent::sequence)
+            .collect();
+        assert_eq!(sequences, [1, 1, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn clock_timestamps() {
+        let mut clock = LogicalClock::default();
+        assert_eq!(clock.now(Some(1711396383937299000)), 1);
+        assert_eq!(clock.now(None), 2);
+
+        let mut clock = InputClock::default();
+        assert_eq!(clock.now(Some(1711396383937299000)), 1711396383937299000);
+        assert_eq!(clock.now(None), 1711396383937299000);
+    }
+
+    #[test]
+    fn publish_top_of_book_changes_per_side() {
+        let mut engine = Engine::new(Vec::new());
+
+        let requests = [
+            Create::new(1, 1, 10, 100, Side::Bid).into(),
+            // Behind the best bid, no change.
+            Create::new(2, 101, 9, 100, Side::Bid).into(),
+            Create::new(2, 102, 11, 100, Side::Ask).into(),
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: None,
+            },
+            OrderRequest::Cancel {
+                user_id: 2,
+                user_order_id: 101,
+                unix_nano: None,
+            },
+            // Silent, like the flushes ending the reference scenarios.
+            OrderRequest::FlushBook { symbol: None },
+            // The flushed book publishes its top afresh.
+            Create::new(2, 102, 11, 100, Side::Ask).into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,B,10,100",
+                "A,2,101",
+                "A,2,102",
+                "B,S,11,100",
+                "A,1,1",
+                "B,B,9,100",
+                "A,2,101",
+                "B,B,-,-",
+                "A,2,102",
+                "B,S,11,100",
+            ]
+        );
+    }
+
+    #[test]
+    fn aggregate_depth() {
+        let mut orderbook = Book::new();
+        for (order_id, price, quantity, side) in [
+            (1, 10, 100, Side::Bid),
+            (2, 10, 50, Side::Bid),
+            (3, 9, 70, Side::Bid),
+            (4, 8, 20, Side::Bid),
+            (5, 12, 80, Side::Ask),
+            (6, 13, 30, Side::Ask),
+        ] {
+            let limit_order = LimitOrder {
+                user_id: 1,
+                order_id,
+                price,
+                quantity,
+                side,
+                order_symbol: "IBM".to_string(),
+                timestamp: order_id as u128,
+                sequence: 0,
+                filled: 0,
+                status: Default::default(),
+                order_type: Default::default(),
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                peak_size: None,
+                replenished_at: 0,
+                stop_price: None,
+            };
+            assert!(orderbook.matching(limit_order).is_ok());
+        }
+
+        assert_eq!(orderbook.depth(&Side::Bid, 2), [(10, 150, 2), (9, 70, 1)]);
+        assert_eq!(orderbook.depth(&Side::Ask, 5), [(12, 80, 1), (13, 30, 1)]);
+        assert!(orderbook.depth(&Side::Ask, 0).is_empty());
+    }
+
+    #[test]
+    fn track_volume_and_levels() {
+        let order = |order_id, price, quantity, side| LimitOrder {
+            user_id: 1,
+            order_id,
+            price,
+            quantity,
+            side,
+            order_symbol: "IBM".to_string(),
+            timestamp: order_id as u128,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+        let mut orderbook = Book::with_mode(MatchingMode::Trade);
+        for limit_order in [
+            order(1, 10, 100, Side::Bid),
+            order(2, 10, 50, Side::Bid),
+            order(3, 9, 70, Side::Bid),
+            order(4, 12, 80, Side::Ask),
+            // Fills the first bid and leaves 20 of the second one.
+            order(5, 10, 130, Side::Ask),
+        ] {
+            assert!(orderbook.matching(limit_order).is_ok());
+        }
+        assert!(orderbook.reduce(&OrderId::new(1, 3), 40).is_some());
+        assert!(orderbook.cancel(&OrderId::new(1, 4)).is_some());
+
+        assert_eq!(orderbook.len(), (0, 2));
+        assert_eq!(orderbook.volume(), (0, 60));
+        assert_eq!(orderbook.depth(&Side::Bid, 5), [(10, 20, 1), (9, 40, 1)]);
+        assert_eq!(orderbook.best_level(&Side::Ask), None);
+
+        // Running totals agree with the resting orders themselves.
+        let bid_volume: u64 = orderbook
+            .iter(&Side::Bid)
+            .map(|order| order.remaining())
+            .sum();
+        assert_eq!(bid_volume, 60);
+
+        orderbook.flush();
+        assert!(orderbook.is_empty());
+        assert_eq!(orderbook.volume(), (0, 0));
+    }
+
+    #[test]
+    fn cancel_within_deep_level() {
+        let order = |order_id, quantity, side| LimitOrder {
+            user_id: 1,
+            order_id,
+            price: 10,
+            quantity,
+            side,
+            order_symbol: "IBM".to_string(),
+            timestamp: order_id as u128,
+            sequence: 0,
+            filled: 0,
+            status: Default::default(),
+            order_type: Default::default(),
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            peak_size: None,
+            replenished_at: 0,
+            stop_price: None,
+        };
+        let mut orderbook = Book::with_mode(MatchingMode::Trade);
+        for order_id in 1..=5 {
+            assert!(orderbook.matching(order(order_id, 10, Side::Bid)).is_ok());
+        }
+
+        for order_id in [2, 4, 5, 1] {
+            assert!(orderbook.cancel(&OrderId::new(1, order_id)).is_some());
+        }
+        assert!(orderbook.cancel(&OrderId::new(1, 4)).is_none());
+        // Takes the place left by a cancelled order, at the back of the queue.
+        assert!(orderbook.matching(order(6, 10, Side::Bid)).is_ok());
+        assert!(orderbook.matching(order(7, 10, Side::Bid)).is_ok());
+
+        let queue: Vec<_> = orderbook
+            .iter(&Side::Bid)
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(queue, [3, 6, 7]);
+        assert_eq!(orderbook.depth(&Side::Bid, 1), [(10, 30, 3)]);
+
+        // Fills follow the time priority of the remaining orders.
+        let (records, _) = orderbook.matching(order(8, 25, Side::Ask)).unwrap();
+        let fills: Vec<_> = records
+            .iter()
+            .filter_map(|event| match event {
+                EngineEvent::Trade(trade) => Some((trade.buy_order_id, trade.amount)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills, [(3, 10), (6, 10), (7, 5)]);
+        assert_eq!(orderbook.depth(&Side::Bid, 1), [(10, 5, 1)]);
+    }
+
+    #[test]
+    fn publish_depth_updates() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade).with_depth_output(true);
+
+        let create = |user_order_id, price, qty, side| {
+            Create::new(1, user_order_id, price, qty, side).into()
+        };
+        let requests = vec![
+            create(1, 10, 100, Side::Bid),
+            // Joins the best bid level, behind the first order.
+            create(2, 10, 50, Side::Bid),
+            create(3, 12, 80, Side::Ask),
+            // Fills the first bid and eats into the second one.
+            create(4, 10, 120, Side::Ask),
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 2,
+                unix_nano: None,
+            },
+            // Deletes every level, including the one left on the ask side.
+            OrderRequest::FlushBook { symbol: None },
+            create(5, 12, 10, Side::Ask),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,B,10,100",
+                "L,A,B,10,100,1",
+                "A,1,2",
+                "B,B,10,150",
+                "L,C,B,10,150,2",
+                "A,1,3",
+                "B,S,12,80",
+                "L,A,S,12,80,1",
+                "A,1,4",
+                "T,1,1,1,4,10,100",
+                "T,1,2,1,4,10,20",
+                "B,B,10,30",
+                "L,C,B,10,30,1",
+                "A,1,2",
+                "B,B,-,-",
+                "L,D,B,10,0,0",
+                "L,D,S,12,0,0",
+                "A,1,5",
+                "B,S,12,10",
+                "L,A,S,12,10,1",
+            ]
+        );
+    }
+
+    #[test]
+    fn publish_order_updates() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade).with_order_output(true);
+
+        let create = |user_order_id, price, qty, side| {
+            Create::new(1, user_order_id, price, qty, side).into()
+        };
+        let requests = vec![
+            create(1, 10, 100, Side::Bid),
+            create(2, 10, 50, Side::Bid),
+            create(3, 10, 40, Side::Bid),
+            // Fills the first bid and eats into the second one.
+            create(4, 10, 120, Side::Ask),
+            OrderRequest::Amend {
+                user_id: 1,
+                user_order_id: 3,
+                price: 10,
+                qty: 30,
+                unix_nano: None,
+            },
+            // Re-queues the order behind the third bid.
+            OrderRequest::Amend {
+                user_id: 1,
+                user_order_id: 2,
+                price: 10,
+                qty: 60,
+                unix_nano: None,
+            },
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 3,
+                unix_nano: None,
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        let records: Vec<_> = render(engine.into_sink())
+            .into_iter()
+            .filter(|record| record.starts_with('O'))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                "O,A,1,1,B,10,100,0",
+                "O,A,1,2,B,10,50,1",
+                "O,A,1,3,B,10,40,2",
+                "O,D,1,1,B,10,0,0",
+                "O,F,1,2,B,10,30,0",
+                "O,R,1,3,B,10,30,1",
+                "O,D,1,2,B,10,30,0",
+                "O,A,1,2,B,10,40,1",
+                "O,D,1,3,B,10,30,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn flush_deletes_order_updates() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade).with_order_output(true);
+
+        let requests = [
+            Create::new(1, 1, 10, 100, Side::Bid).into(),
+            Create::new(1, 2, 10, 50, Side::Bid).into(),
+            Create::new(1, 3, 12, 80, Side::Ask).into(),
+            Create {
+                symbol: "AAPL".to_string(),
+                ..Create::new(2, 1, 20, 10, Side::Ask)
+            }
+            .into(),
+            OrderRequest::FlushBook { symbol: None },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        // Applying the order updates leaves an empty replica.
+        let mut replica = std::collections::BTreeSet::new();
+        for event in engine.sink() {
+            if let EngineEvent::OrderUpdate {
+                action,
+                user_id,
+                user_order_id,
+                ..
+            } = event
+            {
+                match action {
+                    OrderAction::Add => assert!(replica.insert((*user_id, *user_order_id))),
+                    OrderAction::Delete => assert!(replica.remove(&(*user_id, *user_order_id))),
+                    OrderAction::Fill | OrderAction::Reduce => {}
+                }
+            }
+        }
+        assert!(replica.is_empty());
+
+        // Books are flushed by symbol, each level from its head.
+        let records = render(engine.into_sink()).split_off(12);
+        assert_eq!(
+            records,
+            [
+                "O,D,2,1,S,20,10,0",
+                "O,D,1,1,B,10,100,0",
+                "O,D,1,2,B,10,50,0",
+                "O,D,1,3,S,12,80,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn prevent_self_trades() {
+        let run = |mode| {
+            let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+            let requests = [
+                Create::new(1, 1, 10, 50, Side::Bid).into(),
+                Create::new(2, 1, 10, 20, Side::Bid).into(),
+                Create {
+                    self_trade_prevention: Some(mode),
+                    ..Create::new(1, 2, 10, 60, Side::Ask)
+                }
+                .into(),
+            ];
+            for request in requests {
+                assert!(engine.process(request).is_ok());
+            }
+            render(engine.into_sink()).split_off(4)
+        };
+
+        assert_eq!(
+            run(SelfTradePrevention::CancelNewest),
+            ["A,1,2", "P,1,2,1,CN,60,0"]
+        );
+        assert_eq!(
+            run(SelfTradePrevention::CancelOldest),
+            [
+                "A,1,2",
+                "P,1,2,1,CO,0,50",
+                "T,2,1,1,2,10,20",
+                "B,B,-,-",
+                "B,S,10,40",
+            ]
+        );
+        assert_eq!(
+            run(SelfTradePrevention::CancelBoth),
+            ["A,1,2", "P,1,2,1,CB,60,50", "B,B,10,20"]
+        );
+        assert_eq!(
+            run(SelfTradePrevention::DecrementAndCancel),
+            ["A,1,2", "P,1,2,1,DC,50,50", "T,2,1,1,2,10,10", "B,B,10,10"]
+        );
+    }
+
+    #[test]
+    fn prevent_self_trades_per_user() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade)
+            .with_self_trade_prevention(1, SelfTradePrevention::DecrementAndCancel)
+            .with_order_output(true);
+
+        let requests = [
+            Create::new(1, 1, 10, 50, Side::Bid).into(),
+            // Decremented against the larger resting order, which keeps its place.
+            Create::new(1, 2, 10, 20, Side::Ask).into(),
+            // Orders setting their own mode take precedence over the user's.
+            Create {
+                self_trade_prevention: Some(SelfTradePrevention::CancelNewest),
+                ..Create::new(1, 3, 10, 10, Side::Ask)
+            }
+            .into(),
+            // Other users trade as before.
+            Create::new(2, 1, 10, 10, Side::Ask).into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,B,10,50",
+                "O,A,1,1,B,10,50,0",
+                "A,1,2",
+                "P,1,2,1,DC,20,20",
+                "B,B,10,30",
+                "O,R,1,1,B,10,30,0",
+                "A,1,3",
+                "P,1,3,1,CN,10,0",
+                "A,2,1",
+                "T,1,1,2,1,10,10",
+                "B,B,10,20",
+                "O,F,1,1,B,10,20,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn prevent_self_trades_fill_or_kill() {
+        let run = |mode| {
+            let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+            let requests = [
+                Create::new(1, 1, 10, 50, Side::Bid).into(),
+                Create::new(2, 1, 9, 50, Side::Bid).into(),
+                Create {
+                    time_in_force: TimeInForce::Fok,
+                    self_trade_prevention: Some(mode),
+                    ..Create::new(1, 2, 9, 40, Side::Ask)
+                }
+                .into(),
+            ];
+            for request in requests {
+                assert!(engine.process(request).is_ok());
+            }
+            render(engine.into_sink()).split_off(3)
+        };
+
+        // Only orders of other users fill the order, which must not reach past its own.
+        assert_eq!(run(SelfTradePrevention::CancelNewest), ["R,1,2"]);
+        assert_eq!(run(SelfTradePrevention::DecrementAndCancel), ["R,1,2"]);
+        assert_eq!(
+            run(SelfTradePrevention::CancelOldest),
+            ["A,1,2", "P,1,2,1,CO,0,50", "T,2,1,1,2,9,40", "B,B,9,10",]
+        );
+    }
+
+    #[test]
+    fn trade_iceberg_orders() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade).with_order_output(true);
+
+        let requests = [
+            Create {
+                peak_size: Some(30),
+                ..Create::new(1, 1, 10, 100, Side::Ask)
+            }
+            .into(),
+            Create::new(2, 1, 10, 20, Side::Ask).into(),
+            // Fills the peak of the iceberg, which is replenished behind the second ask.
+            Create::new(3, 1, 10, 40, Side::Bid).into(),
+            // Sweeps the level down to the last peak of the iceberg, resting the remainder
+            // with a peak of its own.
+            Create {
+                peak_size: Some(15),
+                ..Create::new(4, 1, 11, 100, Side::Bid)
+            }
+            .into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,S,10,30",
+                "O,A,1,1,S,10,30,0",
+                "A,2,1",
+                "B,S,10,50",
+                "O,A,2,1,S,10,20,1",
+                "A,3,1",
+                "T,3,1,1,1,10,30",
+                "T,3,1,2,1,10,10",
+                "B,S,10,40",
+                "O,D,1,1,S,10,0,0",
+                "O,A,1,1,S,10,30,1",
+                "O,F,2,1,S,10,10,0",
+                "A,4,1",
+                "T,4,1,2,1,10,10",
+                "T,4,1,1,1,10,30",
+                "T,4,1,1,1,10,30",
+                "T,4,1,1,1,10,10",
+                "B,B,11,15",
+                "B,S,-,-",
+                "O,D,2,1,S,10,0,0",
+                "O,D,1,1,S,10,0,0",
+                "O,A,1,1,S,10,30,0",
+                "O,D,1,1,S,10,0,0",
+                "O,A,1,1,S,10,10,0",
+                "O,D,1,1,S,10,0,0",
+                "O,A,4,1,B,11,15,0",
+            ]
+        );
+    }
+
+    #[test]
+    fn reduce_iceberg_orders() {
+        let mut book = Book::with_mode(MatchingMode::Trade);
+        let order = |request: OrderRequest| LimitOrder::try_from(request).unwrap();
+
+        assert!(book
+            .matching(order(
+                Create {
+                    peak_size: Some(30),
+                    ..Create::new(1, 1, 10, 100, Side::Bid)
+                }
+                .into()
+            ))
+            .is_ok());
+        assert_eq!(book.volume(), (0, 30));
+        assert_eq!(book.best_level(&Side::Bid), Some((10, 30)));
+
+        // Only the reserve is taken off until the order is smaller than its peak.
+        book.reduce(&OrderId::new(1, 1), 50);
+        assert_eq!(book.volume(), (0, 30));
+        book.reduce(&OrderId::new(1, 1), 20);
+        assert_eq!(book.volume(), (0, 20));
+        assert_eq!(book.depth(&Side::Bid, 1), [(10, 20, 1)]);
+    }
+
+    #[test]
+    fn fill_or_kill_against_iceberg_reserve() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create {
+                peak_size: Some(10),
+                ..Create::new(1, 1, 10, 100, Side::Ask)
+            }
+            .into(),
+            Create {
+                time_in_force: TimeInForce::Fok,
+                ..Create::new(2, 1, 10, 60, Side::Bid)
+            }
+            .into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()).split_off(2),
+            [
+                "A,2,1",
+                "T,2,1,1,1,10,10",
+                "T,2,1,1,1,10,10",
+                "T,2,1,1,1,10,10",
+                "T,2,1,1,1,10,10",
+                "T,2,1,1,1,10,10",
+                "T,2,1,1,1,10,10",
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_or_kill_against_iceberg_reserve_behind_own_order() {
+        let run = |mode| {
+            let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+            let requests = [
+                Create {
+                    peak_size: Some(10),
+                    ..Create::new(2, 1, 10, 100, Side::Ask)
+                }
+                .into(),
+                Create::new(1, 2, 10, 100, Side::Ask).into(),
+                Create {
+                    time_in_force: TimeInForce::Fok,
+                    self_trade_prevention: Some(mode),
+                    ..Create::new(1, 3, 10, 50, Side::Bid)
+                }
+                .into(),
+            ];
+            for request in requests {
+                assert!(engine.process(request).is_ok());
+            }
+            render(engine.into_sink()).split_off(4)
+        };
+
+        // The reserve is shown again behind the order of the same user, which stops the
+        // matching after the first peak.
+        assert_eq!(run(SelfTradePrevention::CancelNewest), ["R,1,3"]);
+        assert_eq!(run(SelfTradePrevention::DecrementAndCancel), ["R,1,3"]);
+        assert_eq!(
+            run(SelfTradePrevention::CancelOldest),
+            [
+                "A,1,3",
+                "T,1,3,2,1,10,10",
+                "P,1,3,2,CO,0,100",
+                "T,1,3,2,1,10,10",
+                "T,1,3,2,1,10,10",
+                "T,1,3,2,1,10,10",
+                "T,1,3,2,1,10,10",
+                "B,S,10,10",
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_iceberg_without_peak() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        assert!(engine
+            .process(
+                Create {
+                    peak_size: Some(0),
+                    ..Create::new(1, 1, 10, 100, Side::Ask)
+                }
+                .into()
+            )
+            .is_ok());
+        assert_eq!(render(engine.into_sink()), ["R,1,1,invalid peak size"]);
+    }
+
+    #[test]
+    fn trigger_stop_orders() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create::new(1, 1, 10, 10, Side::Ask).into(),
+            Create::new(1, 2, 12, 10, Side::Ask).into(),
+            Create {
+                stop_price: Some(11),
+                ..Create::new(2, 1, 0, 5, Side::Bid)
+            }
+            .into(),
+            Create {
+                stop_price: Some(10),
+                ..Create::new(3, 1, 12, 10, Side::Bid)
+            }
+            .into(),
+            // Trades at 10, triggering the stop-limit order, whose trade at 12 triggers the
+            // stop-market order in turn.
+            Create::new(4, 1, 10, 5, Side::Bid).into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "B,S,10,10",
+                "A,1,2",
+                "A,2,1",
+                "A,3,1",
+                "A,4,1",
+                "T,4,1,1,1,10,5",
+                "S,3,1,10",
+                "T,3,1,1,1,10,5",
+                "T,3,1,1,2,12,5",
+                "S,2,1,11",
+                "T,2,1,1,2,12,5",
+                "B,S,-,-",
+            ]
+        );
+    }
+
+    #[test]
+    fn trigger_stop_orders_in_order() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create::new(1, 1, 10, 100, Side::Bid).into(),
+            Create {
+                stop_price: Some(9),
+                ..Create::new(2, 1, 0, 10, Side::Ask)
+            }
+            .into(),
+            Create {
+                stop_price: Some(10),
+                ..Create::new(3, 1, 0, 10, Side::Ask)
+            }
+            .into(),
+            Create {
+                stop_price: Some(10),
+                ..Create::new(4, 1, 0, 10, Side::Ask)
+            }
+            .into(),
+            Create {
+                stop_price: Some(11),
+                ..Create::new(5, 1, 0, 10, Side::Ask)
+            }
+            .into(),
+            Create::new(6, 1, 10, 10, Side::Ask).into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        // The highest sell stop is reached first, and stops of the same price trigger in the
+        // order they arrived. The last trade price never falls to the lowest stop.
+        assert_eq!(
+            render(engine.into_sink()).split_off(6),
+            [
+                "A,6,1",
+                "T,1,1,6,1,10,10",
+                "S,5,1,11",
+                "T,1,1,5,1,10,10",
+                "S,3,1,10",
+                "T,1,1,3,1,10,10",
+                "S,4,1,10",
+                "T,1,1,4,1,10,10",
+                "B,B,10,60",
+            ]
+        );
+    }
+
+    #[test]
+    fn manage_pending_stop_orders() {
+        let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade);
+
+        let requests = [
+            Create {
+                stop_price: Some(11),
+                ..Create::new(1, 1, 10, 10, Side::Bid)
+            }
+            .into(),
+            OrderRequest::Amend {
+                user_id: 1,
+                user_order_id: 1,
+                price: 11,
+                qty: 10,
+                unix_nano: None,
+            },
+            OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: None,
+            },
+            Create {
+                stop_price: Some(0),
+                ..Create::new(2, 1, 10, 10, Side::Bid)
+            }
+            .into(),
+            Create {
+                time_in_force: TimeInForce::Day,
+                stop_price: Some(9),
+                ..Create::new(3, 1, 10, 10, Side::Ask)
+            }
+            .into(),
+            Create {
+                stop_price: Some(11),
+                ..Create::new(4, 1, 10, 10, Side::Bid)
+            }
+            .into(),
+            OrderRequest::EndOfSession,
+            // The stop order of the fourth user still holds its id.
+            Create::new(4, 1, 10, 10, Side::Bid).into(),
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        assert_eq!(
+            render(engine.into_sink()),
+            [
+                "A,1,1",
+                "R,1,1,invalid amend",
+                "A,1,1",
+                "R,2,1,invalid stop price",
+                "A,3,1",
+                "A,4,1",
+                "A,3,1",
+                "R,4,1,duplicate order id",
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_stop_orders_without_trading() {
+        let mut engine = Engine::new(Vec::new());
+
+        let request = Create {
+            stop_price: Some(11),
+            ..Create::new(1, 1, 10, 10, Side::Bid)
+        };
+        assert!(engine.process(request.into()).is_ok());
+        assert_eq!(
+            render(engine.into_sink()),
+            ["R,1,1,stop orders require trade mode"]
+        );
+    }
+
+    #[test]
+    fn publish_events_to_channel() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut engine = Engine::new(tx);
+
+        let request = Create::new(1, 1, 10, 100, Side::Bid).into();
+        assert!(engine.process(request).is_ok());
+        assert!(engine
+            .publish_error("line 2: unknown record type `X`")
+            .is_ok());
+        drop(engine);
+
+        let events: Vec<_> = rx.iter().collect();
+        assert_eq!(
+            events.iter().map(label).collect::<Vec<_>>(),
+            ["A", "B", "E"]
+        );
+        assert!(matches!(
+            &events[2],
+            EngineEvent::Error { sequence: 2, message } if message == "line 2: unknown record type `X`"
+        ));
+        // Errors are reported on stderr rather than written as records.
+        assert_eq!(render(events), ["A,1,1", "B,B,10,100"]);
+    }
+
+    #[test]
+    fn publish_typed_events() {
+        let mut engine = Engine::new(Vec::new()).with_symbol_output(true);
+
+        let requests = [
+            Create::new(1, 1, 10, 100, Side::Ask).into(),
+            OrderRequest::FlushBook {
+                symbol: Some("IBM".to_string()),
+            },
+        ];
+        for request in requests {
+            assert!(engine.process(request).is_ok());
+        }
+
+        let events = engine.into_sink();
+        assert_eq!(
+            events,
+            [
+                EngineEvent::Accepted {
+                    user_id: 1,
+                    user_order_id: 1,
+                    sequence: 1,
+                },
+                EngineEvent::TopOfBook {
+                    side: Side::Ask,
+                    price: Some(10),
+                    qty: Some(100),
+                    symbol: Some("IBM".to_string()),
+                    sequence: 1,
+                },
+                EngineEvent::Flushed {
+                    symbol: Some("IBM".to_string()),
+                    sequence: 2,
+                },
+            ]
+        );
+        // Flushes have no CSV record.
+        assert_eq!(render(events), ["A,1,1", "B,S,10,100,IBM"]);
+    }
 }
@@ -0,0 +1,170 @@
+mod common;
+
+use common::Create;
+use orderbook::binary::{DecodeError, EventReader};
+use orderbook::core::order::SelfTradePrevention;
+use orderbook::core::{Engine, MatchingMode, OrderRequest, Side};
+use orderbook::event::EngineEvent;
+use orderbook::sink::{BinarySink, EventSink, JsonLinesSink};
+
+fn events() -> Vec<EngineEvent> {
+    let mut engine = Engine::with_mode(Vec::new(), MatchingMode::Trade)
+        .with_symbol_output(true)
+        .with_depth_output(true)
+        .with_order_output(true);
+
+    let requests = [
+        Create::new(1, 1, 10, 100, Side::Bid).into(),
+        Create::new(2, 101, 10, 100, Side::Ask).into(),
+        Create::new(2, 102, 12, 10, Side::Ask).into(),
+        Create {
+            self_trade_prevention: Some(SelfTradePrevention::CancelNewest),
+            ..Create::new(2, 103, 12, 5, Side::Bid)
+        }
+        .into(),
+        // Triggered on entry by the last trade, with no bid left to sell to.
+        Create {
+            stop_price: Some(12),
+            ..Create::new(2, 104, 0, 5, Side::Ask)
+        }
+        .into(),
+        OrderRequest::Cancel {
+            user_id: 2,
+            user_order_id: 1,
+            unix_nano: None,
+        },
+        OrderRequest::FlushBook { symbol: None },
+    ];
+    for request in requests {
+        assert!(engine.process(request).is_ok());
+    }
+    assert!(engine
+        .publish_error("line 8: unknown record type `X`")
+        .is_ok());
+
+    engine.into_sink()
+}
+
+mod valid {
+    use super::*;
+
+    #[test]
+    fn binary_round_trip() {
+        let events = events();
+
+        let mut sink = BinarySink::new(vec![]);
+        for event in events.clone() {
+            sink.publish(event).unwrap();
+        }
+        let bytes = sink.into_inner().unwrap();
+
+        let decoded: Vec<_> = EventReader::new(bytes.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(decoded, events);
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut sink = JsonLinesSink::new(vec![]);
+        for event in events() {
+            sink.publish(event).unwrap();
+        }
+        let output = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            output.lines().collect::<Vec<_>>(),
+            [
+                r#"{"event":"accepted","user_id":1,"user_order_id":1,"sequence":1}"#,
+                r#"{"event":"top_of_book","side":"B","price":10,"qty":100,"symbol":"IBM","sequence":1}"#,
+                r#"{"event":"depth_update","action":"add","side":"B","price":10,"qty":100,"orders":1,"symbol":"IBM","sequence":1}"#,
+                r#"{"event":"order_update","action":"add","user_id":1,"user_order_id":1,"side":"B","price":10,"qty":100,"position":0,"symbol":"IBM","sequence":1}"#,
+                r#"{"event":"accepted","user_id":2,"user_order_id":101,"sequence":2}"#,
+                r#"{"event":"trade","buy_user_id":1,"buy_order_id":1,"sell_user_id":2,"sell_order_id":101,"price":10,"amount":100,"sequence":2}"#,
+                r#"{"event":"top_of_book","side":"B","price":null,"qty":null,"symbol":"IBM","sequence":2}"#,
+                r#"{"event":"depth_update","action":"delete","side":"B","price":10,"qty":0,"orders":0,"symbol":"IBM","sequence":2}"#,
+                r#"{"event":"order_update","action":"delete","user_id":1,"user_order_id":1,"side":"B","price":10,"qty":0,"position":0,"symbol":"IBM","sequence":2}"#,
+                r#"{"event":"accepted","user_id":2,"user_order_id":102,"sequence":3}"#,
+                r#"{"event":"top_of_book","side":"S","price":12,"qty":10,"symbol":"IBM","sequence":3}"#,
+                r#"{"event":"depth_update","action":"add","side":"S","price":12,"qty":10,"orders":1,"symbol":"IBM","sequence":3}"#,
+                r#"{"event":"order_update","action":"add","user_id":2,"user_order_id":102,"side":"S","price":12,"qty":10,"position":0,"symbol":"IBM","sequence":3}"#,
+                r#"{"event":"accepted","user_id":2,"user_order_id":103,"sequence":4}"#,
+                r#"{"event":"self_trade_prevented","user_id":2,"taker_order_id":103,"maker_order_id":102,"mode":"CN","taker_qty":5,"maker_qty":0,"sequence":4}"#,
+                r#"{"event":"accepted","user_id":2,"user_order_id":104,"sequence":5}"#,
+                r#"{"event":"triggered","user_id":2,"user_order_id":104,"stop_price":12,"sequence":5}"#,
+                r#"{"event":"rejected","user_id":2,"user_order_id":104,"sequence":5}"#,
+                r#"{"event":"rejected","user_id":2,"user_order_id":1,"reason":"unknown order","sequence":6}"#,
+                r#"{"event":"flushed","sequence":7}"#,
+                r#"{"event":"depth_update","action":"delete","side":"S","price":12,"qty":0,"orders":0,"symbol":"IBM","sequence":7}"#,
+                r#"{"event":"order_update","action":"delete","user_id":2,"user_order_id":102,"side":"S","price":12,"qty":10,"position":0,"symbol":"IBM","sequence":7}"#,
+                r#"{"event":"error","message":"line 8: unknown record type `X`","sequence":8}"#,
+            ]
+        );
+    }
+}
+
+mod invalid {
+    use super::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn truncated_frame() {
+        let mut sink = BinarySink::new(vec![]);
+        for event in events() {
+            sink.publish(event).unwrap();
+        }
+        let mut bytes = sink.into_inner().unwrap();
+        bytes.pop();
+
+        let decoded: Vec<_> = EventReader::new(bytes.as_slice()).collect();
+        assert_eq!(decoded.len(), 23);
+        assert!(decoded[..22].iter().all(Result::is_ok));
+        assert!(matches!(decoded[22], Err(DecodeError::Truncated)));
+    }
+
+    #[test]
+    fn oversized_frame() {
+        // A length prefix of 4 GiB, followed by the start of an event.
+        let bytes = [0xff, 0xff, 0xff, 0xff, 1];
+
+        let decoded: Vec<_> = EventReader::new(&bytes[..]).collect();
+        assert!(matches!(
+            decoded[..],
+            [Err(DecodeError::TooLarge(0xffff_ffff))]
+        ));
+    }
+
+    #[test]
+    fn invalid_order_action() {
+        let mut sink = BinarySink::new(vec![]);
+        for event in events() {
+            sink.publish(event).unwrap();
+        }
+        let mut bytes = sink.into_inner().unwrap();
+        // Skips the three frames before the first order update to reach its action.
+        let mut offset = 0;
+        for _ in 0..3 {
+            offset +=
+                4 + u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        }
+        bytes[offset + 5] = b'X';
+
+        let decoded: Vec<_> = EventReader::new(bytes.as_slice()).collect();
+        assert!(matches!(
+            decoded[3],
+            Err(DecodeError::InvalidOrderAction(b'X'))
+        ));
+        assert_eq!(
+            DecodeError::InvalidOrderAction(b'X').to_string(),
+            "invalid order action `88`"
+        );
+    }
+
+    #[test]
+    fn unknown_tag() {
+        let bytes = [1, 0, 0, 0, 42];
+
+        let decoded: Vec<_> = EventReader::new(&bytes[..]).collect();
+        assert!(matches!(decoded[..], [Err(DecodeError::UnknownTag(42))]));
+    }
+}
@@ -0,0 +1,184 @@
+use orderbook::core::order::{SelfTradePrevention, TimeInForce};
+use orderbook::core::{OrderRequest, Side};
+use orderbook::parser::{JsonRequestParser, ParseError, RequestParser};
+
+mod valid {
+    use super::*;
+
+    #[test]
+    fn parse_records() {
+        let input = "\
+# comment
+N, 1, IBM, 10, 100, B, 1
+
+C, 1, 1
+F
+N, 1, IBM, 10, 100, B, 2, IOC, , DC, 20, 11
+";
+        let requests: Vec<_> = RequestParser::new(input.as_bytes()).collect();
+        assert_eq!(requests.len(), 4);
+        assert!(matches!(
+            requests[0],
+            Ok(OrderRequest::Create {
+                user_id: 1,
+                price: 10,
+                qty: 100,
+                user_order_id: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            requests[1],
+            Ok(OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            requests[2],
+            Ok(OrderRequest::FlushBook { symbol: None })
+        ));
+        assert!(matches!(
+            requests[3],
+            Ok(OrderRequest::Create {
+                user_order_id: 2,
+                time_in_force: TimeInForce::Ioc,
+                self_trade_prevention: Some(SelfTradePrevention::DecrementAndCancel),
+                peak_size: Some(20),
+                stop_price: Some(11),
+                unix_nano: None,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_json_lines() {
+        let input = r#"
+{"type":"create","user_id":1,"symbol":"IBM, Inc","price":10,"qty":100,"side":"B","user_order_id":1}
+{"type":"create","user_id":2,"symbol":"IBM","price":0,"qty":50,"side":"S","user_order_id":2,"time_in_force":"IOC","self_trade_prevention":"CO","peak_size":25,"stop_price":9,"unix_nano":1711396383937299000}
+
+{"type":"cancel","user_id":1,"user_order_id":1}
+{"type":"amend","user_id":1,"user_order_id":1,"price":11,"qty":80,"unix_nano":1711396383937305000}
+{"type":"flush_book","symbol":"IBM"}
+{"type":"end_of_session"}
+"#;
+        let requests: Vec<_> = JsonRequestParser::new(input.as_bytes()).collect();
+        assert_eq!(requests.len(), 6);
+        assert!(matches!(
+            &requests[0],
+            Ok(OrderRequest::Create {
+                user_id: 1,
+                symbol,
+                price: 10,
+                qty: 100,
+                side: Side::Bid,
+                user_order_id: 1,
+                time_in_force: TimeInForce::Gtc,
+                self_trade_prevention: None,
+                peak_size: None,
+                stop_price: None,
+                unix_nano: None,
+            }) if symbol == "IBM, Inc"
+        ));
+        assert!(matches!(
+            requests[1],
+            Ok(OrderRequest::Create {
+                price: 0,
+                side: Side::Ask,
+                time_in_force: TimeInForce::Ioc,
+                self_trade_prevention: Some(SelfTradePrevention::CancelOldest),
+                peak_size: Some(25),
+                stop_price: Some(9),
+                unix_nano: Some(1711396383937299000),
+                ..
+            })
+        ));
+        assert!(matches!(
+            requests[2],
+            Ok(OrderRequest::Cancel {
+                user_id: 1,
+                user_order_id: 1,
+                unix_nano: None,
+            })
+        ));
+        assert!(matches!(
+            requests[3],
+            Ok(OrderRequest::Amend {
+                price: 11,
+                qty: 80,
+                unix_nano: Some(1711396383937305000),
+                ..
+            })
+        ));
+        assert!(matches!(
+            &requests[4],
+            Ok(OrderRequest::FlushBook { symbol: Some(symbol) }) if symbol == "IBM"
+        ));
+        assert!(matches!(requests[5], Ok(OrderRequest::EndOfSession)));
+    }
+}
+
+mod invalid {
+    use super::*;
+
+    #[test]
+    fn report_errors_and_continue() {
+        let input = "\
+N, 1, IBM, abc, 100, B, 1
+
+X, 1
+C, 1
+N, 1, IBM, 10, 100, B, 2
+N, 1, IBM, 10, 100, B, 3, GTC, , XX
+";
+        let requests: Vec<_> = RequestParser::new(input.as_bytes()).collect();
+        assert_eq!(requests.len(), 5);
+        assert!(matches!(
+            &requests[0],
+            Err(ParseError::InvalidField { line: 1, field: "price", value }) if value == "abc"
+        ));
+        assert!(matches!(
+            &requests[1],
+            Err(ParseError::UnknownRecord { line: 3, value }) if value == "X"
+        ));
+        assert!(matches!(
+            requests[2],
+            Err(ParseError::MissingField {
+                line: 4,
+                field: "userOrderId"
+            })
+        ));
+        assert!(matches!(
+            requests[3],
+            Ok(OrderRequest::Create {
+                user_order_id: 2,
+                ..
+            })
+        ));
+        assert!(matches!(
+            &requests[4],
+            Err(ParseError::InvalidField { line: 6, field: "selfTradePrevention", value }) if value == "XX"
+        ));
+    }
+
+    #[test]
+    fn report_json_errors_and_continue() {
+        let input = r#"{"type":"bogus"}
+
+{"type":"cancel","user_id":1}
+{"type":"cancel","user_id":1,"user_order_id":1
+{"type":"cancel","user_id":1,"user_order_id":1}
+"#;
+        let requests: Vec<_> = JsonRequestParser::new(input.as_bytes()).collect();
+        assert_eq!(requests.len(), 4);
+        assert!(matches!(requests[0], Err(ParseError::Json { line: 1, .. })));
+        assert_eq!(
+            requests[1].as_ref().unwrap_err().to_string(),
+            "line 3: missing field `user_order_id`"
+        );
+        assert!(matches!(requests[2], Err(ParseError::Json { line: 4, .. })));
+        assert!(matches!(requests[3], Ok(OrderRequest::Cancel { .. })));
+    }
+}
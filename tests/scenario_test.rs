@@ -0,0 +1,79 @@
+use orderbook::core::MatchingMode;
+use orderbook::scenario::{verify, DiffLine, Outcome};
+
+fn read(file_name: &str) -> String {
+    std::fs::read_to_string(format!("{}/etc/{}", env!("CARGO_MANIFEST_DIR"), file_name)).unwrap()
+}
+
+mod valid {
+    use super::*;
+
+    #[test]
+    fn golden_scenarios() {
+        let reports = verify(
+            &read("input_file.csv"),
+            &read("output_file.csv"),
+            MatchingMode::Reject,
+        );
+
+        for report in &reports {
+            assert!(!report.is_failure(), "{}", report);
+        }
+        let passed = reports
+            .iter()
+            .filter(|report| report.outcome == Outcome::Passed)
+            .count();
+        assert_eq!(passed, 12);
+    }
+
+    #[test]
+    fn golden_trade_scenarios() {
+        let reports = verify(
+            &read("trade_input_file.csv"),
+            &read("output_file.csv"),
+            MatchingMode::Trade,
+        );
+
+        for report in &reports {
+            assert!(!report.is_failure(), "{}", report);
+        }
+        let passed: Vec<_> = reports
+            .iter()
+            .filter(|report| report.outcome == Outcome::Passed)
+            .map(|report| report.name.as_str())
+            .collect();
+        assert_eq!(passed, ["scenario 13", "scenario 14"]);
+    }
+}
+
+mod invalid {
+    use super::*;
+
+    #[test]
+    fn report_line_diff() {
+        let input = "\
+#name: scenario 1
+N, 1, IBM, 10, 100, B, 1
+N, 1, IBM, 12, 100, S, 2
+";
+        let expected = "\
+#name: scenario  1
+A, 1, 1
+B, B, 10, 100
+A, 1, 2
+B, S, 11, 100
+";
+        let reports = verify(input, expected, MatchingMode::Reject);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            reports[0].outcome,
+            Outcome::Failed(vec![
+                DiffLine::Same("A,1,1".to_owned()),
+                DiffLine::Same("B,B,10,100".to_owned()),
+                DiffLine::Same("A,1,2".to_owned()),
+                DiffLine::Unexpected("B,S,12,100".to_owned()),
+                DiffLine::Missing("B,S,11,100".to_owned()),
+            ])
+        );
+    }
+}
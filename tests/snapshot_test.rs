@@ -0,0 +1,240 @@
+mod common;
+
+use common::Create;
+use orderbook::core::snapshot::{EngineSnapshot, SnapshotError, SNAPSHOT_VERSION};
+use orderbook::core::{Engine, MatchingMode, OrderRequest, Side};
+use orderbook::event::EngineEvent;
+
+fn trading_engine() -> Engine<Vec<EngineEvent>> {
+    Engine::with_mode(Vec::new(), MatchingMode::Trade)
+}
+
+/// Round trips a snapshot through its file format.
+fn reload(snapshot: &EngineSnapshot) -> Result<EngineSnapshot, SnapshotError> {
+    let mut file = Vec::new();
+    snapshot.write(&mut file)?;
+    EngineSnapshot::read(file.as_slice())
+}
+
+mod valid {
+    use super::*;
+
+    #[test]
+    fn restored_engine_matches_identically() {
+        let mut original = trading_engine();
+        let setup = [
+            Create::new(1, 1, 10, 100, Side::Bid).into(),
+            Create::new(2, 2, 10, 50, Side::Bid).into(),
+            Create::new(3, 3, 9, 70, Side::Bid).into(),
+            Create::new(4, 4, 12, 80, Side::Ask).into(),
+            Create {
+                symbol: "VAL".to_string(),
+                ..Create::new(5, 5, 20, 10, Side::Ask)
+            }
+            .into(),
+            // Partially fills the first bid, which keeps its place at the head of the level.
+            Create::new(6, 6, 10, 30, Side::Ask).into(),
+        ];
+        for request in setup {
+            assert!(original.process(request).is_ok());
+        }
+
+        let mut restored = trading_engine();
+        assert!(restored
+            .restore(reload(&original.snapshot()).unwrap())
+            .is_ok());
+        assert_eq!(restored.sequence(), original.sequence());
+
+        let original_events = std::mem::take(original.sink_mut());
+        assert!(original_events.len() > 6);
+
+        let requests = || {
+            [
+                Create::new(7, 7, 9, 200, Side::Ask).into(),
+                Create {
+                    symbol: "VAL".to_string(),
+                    ..Create::new(8, 8, 21, 10, Side::Bid)
+                }
+                .into(),
+                Create::new(9, 9, 12, 10, Side::Bid).into(),
+                OrderRequest::Cancel {
+                    user_id: 4,
+                    user_order_id: 4,
+                    unix_nano: None,
+                },
+            ]
+        };
+        for request in requests() {
+            assert!(original.process(request).is_ok());
+        }
+        for request in requests() {
+            assert!(restored.process(request).is_ok());
+        }
+
+        let events = restored.into_sink();
+        assert_eq!(events, original.into_sink());
+        // Fills follow the restored time priority, starting with the partially filled bid.
+        let fills: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                EngineEvent::Trade(trade) if trade.sell_user_id == 7 => {
+                    Some((trade.buy_user_id, trade.amount))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(fills, [(1, 70), (2, 50), (3, 70), (9, 10)]);
+    }
+
+    #[test]
+    fn restored_stop_orders_are_triggered() {
+        let mut original = trading_engine();
+        let setup = [
+            Create::new(1, 1, 10, 10, Side::Ask).into(),
+            Create::new(2, 2, 10, 5, Side::Bid).into(),
+            Create {
+                stop_price: Some(9),
+                ..Create::new(3, 3, 0, 5, Side::Ask)
+            }
+            .into(),
+            Create {
+                stop_price: Some(8),
+                ..Create::new(4, 4, 0, 5, Side::Ask)
+            }
+            .into(),
+            Create::new(5, 5, 9, 10, Side::Bid).into(),
+        ];
+        for request in setup {
+            assert!(original.process(request).is_ok());
+        }
+
+        let snapshot = reload(&original.snapshot()).unwrap();
+        assert_eq!(snapshot.stops["IBM"].len(), 2);
+        assert_eq!(snapshot.last_trade_prices["IBM"], 10);
+
+        let mut restored = trading_engine();
+        assert!(restored.restore(snapshot).is_ok());
+        std::mem::take(original.sink_mut());
+
+        // Trades at 9, triggering the first stop order only.
+        let request = || Create::new(6, 6, 9, 5, Side::Ask).into();
+        assert!(original.process(request()).is_ok());
+        assert!(restored.process(request()).is_ok());
+
+        let events = restored.into_sink();
+        assert_eq!(events, original.into_sink());
+        assert!(events.contains(&EngineEvent::Triggered {
+            user_id: 3,
+            user_order_id: 3,
+            stop_price: 9,
+            sequence: 6,
+        }));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, EngineEvent::Triggered { user_id: 4, .. })));
+    }
+
+    #[test]
+    fn restored_tops_are_not_republished() {
+        let mut original = trading_engine();
+        assert!(original
+            .process(Create::new(1, 1, 10, 100, Side::Bid).into())
+            .is_ok());
+
+        let mut restored = trading_engine();
+        assert!(restored.restore(original.snapshot()).is_ok());
+        assert!(restored
+            .process(Create::new(2, 2, 11, 100, Side::Ask).into())
+            .is_ok());
+
+        assert_eq!(
+            restored.into_sink(),
+            [
+                EngineEvent::Accepted {
+                    user_id: 2,
+                    user_order_id: 2,
+                    sequence: 2,
+                },
+                EngineEvent::TopOfBook {
+                    side: Side::Ask,
+                    price: Some(11),
+                    qty: Some(100),
+                    symbol: None,
+                    sequence: 2,
+                },
+            ]
+        );
+    }
+}
+
+mod invalid {
+    use super::*;
+
+    fn snapshot() -> EngineSnapshot {
+        let mut engine = trading_engine();
+        assert!(engine
+            .process(Create::new(1, 1, 10, 100, Side::Bid).into())
+            .is_ok());
+        engine.snapshot()
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut snapshot = snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+
+        assert!(matches!(
+            reload(&snapshot),
+            Err(SnapshotError::UnsupportedVersion(version)) if version == SNAPSHOT_VERSION + 1
+        ));
+        assert!(matches!(
+            trading_engine().restore(snapshot),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_order() {
+        let mut snapshot = snapshot();
+        let book = snapshot.books["IBM"].clone();
+        snapshot.books.insert("VAL".to_string(), book);
+
+        assert!(matches!(
+            trading_engine().restore(snapshot),
+            Err(SnapshotError::DuplicateOrder(order_id)) if order_id.user_order_id == 1
+        ));
+    }
+
+    #[test]
+    fn duplicate_stop_order() {
+        let mut snapshot = snapshot();
+        let mut order = snapshot.books["IBM"].bids[0].orders[0].clone();
+        order.stop_price = Some(11);
+        snapshot.stops.insert("VAL".to_string(), vec![order]);
+
+        assert!(matches!(
+            trading_engine().restore(snapshot),
+            Err(SnapshotError::DuplicateOrder(order_id)) if order_id.user_order_id == 1
+        ));
+    }
+
+    #[test]
+    fn misplaced_order() {
+        let mut snapshot = snapshot();
+        let book = snapshot.books.get_mut("IBM").unwrap();
+        book.bids[0].price = 11;
+
+        assert!(matches!(
+            trading_engine().restore(snapshot),
+            Err(SnapshotError::MisplacedOrder(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_file() {
+        assert!(matches!(
+            EngineSnapshot::read(&b"{\"version\": 1"[..]),
+            Err(SnapshotError::Json(_))
+        ));
+    }
+}